@@ -1,5 +1,3 @@
-/// Used by laspa to print to the console.
-#[no_mangle]
-pub extern "C" fn print_f64(value: f64) {
-    println!("{}", value);
-}
\ No newline at end of file
+//! The runtime library linked into programs compiled by laspa.
+
+pub mod runtime;
@@ -0,0 +1,104 @@
+//! Functions called by compiled läspa programs. Every function is exported unmangled as `laspa_<name>`
+//! so the LLVM backend can declare it by name, link it into executables from `liblaspa_std.a`, and map it
+//! into the JIT.
+
+use std::{
+    cell::Cell,
+    io::BufRead,
+    sync::OnceLock,
+    time::{Instant, SystemTime},
+};
+
+/// Print a number on its own line. Used by `print`.
+#[no_mangle]
+pub extern "C" fn laspa_print_f64(value: f64) {
+    println!("{}", value);
+}
+
+/// The square root of a number. Used by the `sqrt` builtin.
+#[no_mangle]
+pub extern "C" fn laspa_sqrt(value: f64) -> f64 {
+    value.sqrt()
+}
+
+/// The length of the hypotenuse of a right triangle with sides `a` and `b`, without overflow or underflow in
+/// the squares. Used by the `hypot` builtin.
+#[no_mangle]
+pub extern "C" fn laspa_hypot(a: f64, b: f64) -> f64 {
+    a.hypot(b)
+}
+
+/// The distance between the points `(x1, y1)` and `(x2, y2)`. Used by the `dist` builtin.
+#[no_mangle]
+pub extern "C" fn laspa_dist(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+    laspa_hypot(x2 - x1, y2 - y1)
+}
+
+/// Read a number from a line of stdin. Returns NaN if the line is not a number or stdin is closed.
+#[no_mangle]
+pub extern "C" fn laspa_read_f64() -> f64 {
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(_) => line.trim().parse().unwrap_or(f64::NAN),
+        Err(_) => f64::NAN,
+    }
+}
+
+/// Seconds since the first call, from a monotonic clock, so the difference of two calls times the code
+/// between them. Used by the `clock` builtin.
+#[no_mangle]
+pub extern "C" fn laspa_clock() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+thread_local! {
+    /// The state of the xorshift generator behind `rand` and `randint`. Zero until it is seeded.
+    static RAND_STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Seed the generator used by `rand` and `randint`, so a program draws the same numbers on every run.
+#[no_mangle]
+pub extern "C" fn laspa_seed_rand(seed: u64) {
+    RAND_STATE.with(|state| state.set(splitmix64(seed)));
+}
+
+/// A uniform random number in `[0, 1)`. Used by the `rand` builtin. An unseeded generator is seeded from
+/// the clock on first use.
+#[no_mangle]
+pub extern "C" fn laspa_rand() -> f64 {
+    RAND_STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            x = splitmix64(nanos);
+        }
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        // the top 53 bits fill the mantissa exactly
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+/// A uniform random integer from `lo` to `hi`, both included. Used by the `randint` builtin. Returns NaN if
+/// there is no integer in the range.
+#[no_mangle]
+pub extern "C" fn laspa_randint(lo: f64, hi: f64) -> f64 {
+    let (lo, hi) = (lo.ceil(), hi.floor());
+    if lo.is_nan() || hi.is_nan() || lo > hi {
+        return f64::NAN;
+    }
+    (lo + (laspa_rand() * (hi - lo + 1.0)).floor()).min(hi)
+}
+
+/// Mix a seed into a state for xorshift, which must not be zero.
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)).max(1)
+}
@@ -0,0 +1,54 @@
+use std::io::sink;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use laspa::{parse_source, Compile, CompileConfig, Compiler, Interpreter, Syntax};
+
+const FIB: &str = "fn fib (n)
+    let r n
+    let m 0
+    let a 0
+    if > n 1
+        := m - n 1
+        := a fib (m)
+        := m - n 2
+        := r + a fib (m)
+    end
+    return r
+end
+return fib (30)";
+
+const COLLATZ: &str = include_str!("../../examples/collatz.laspa");
+
+const LOOP: &str = "let sum 0
+for i 0 100000
+    := sum + sum % * i 7 13
+end
+return sum";
+
+fn interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+    group.sample_size(10);
+    for (name, source) in [("fib 30", FIB), ("collatz", COLLATZ), ("loop", LOOP)] {
+        let nodes = parse_source(source, Syntax::Rpn).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| Interpreter::new().run_ast(&nodes, &mut sink()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn llvm_jit(c: &mut Criterion) {
+    let config = CompileConfig::from(true, false);
+    let mut group = c.benchmark_group("llvm jit");
+    group.sample_size(10);
+    // compiling is measured too, since every run builds a new module
+    for (name, source) in [("fib 30", FIB), ("loop", LOOP)] {
+        group.bench_function(name, |b| {
+            b.iter(|| Compiler::from_source(source, &config).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, interpreter, llvm_jit);
+criterion_main!(benches);
@@ -1,6 +1,29 @@
 use clap::Parser;
+use clap::ValueEnum;
 use clap::ValueHint;
 
+/// What to do with the file
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Interpret the file
+    Interpret,
+    /// Compile the file and execute it with the LLVM JIT
+    Jit,
+    /// Write the object file (<executable_name>.o) without linking it
+    Object,
+    /// Compile the file to an executable
+    Executable,
+    /// Compile the file to an executable and run it
+    Run,
+}
+
+/// Another format to write the compiled module in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// LLVM bitcode (<executable_name>.bc), instead of an object file or executable
+    Bitcode,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(
     author,
@@ -9,27 +32,112 @@ use clap::ValueHint;
     long_about = "A simple Lisp-like language built with Rust. It is a toy language and is not meant to be used in production, but it features JIT and AOT compilation with LLVM"
 )]
 pub struct Args {
-    /// The file to build
-    #[clap(value_name = "FILE", value_hint = ValueHint::FilePath)]
-    pub file: String,
+    /// The file to build, or `-` to read the program from stdin
+    #[clap(value_name = "FILE", value_hint = ValueHint::FilePath, required_unless_present_any = ["backend_info", "eval"])]
+    pub file: Option<String>,
+
+    /// Run this source instead of a file and print the result. Interprets unless `--mode jit` is given
+    #[clap(short, long, value_name = "SOURCE", conflicts_with = "file")]
+    pub eval: Option<String>,
 
     /// Optimization level for the compiler
     #[clap(short = 'O', long, default_value = "1")]
     pub optimization_level: u8,
 
-    /// Interpret the file
-    #[clap(short, long)]
-    pub interpret: bool,
+    /// What to do with the file
+    #[clap(short, long, value_enum, default_value_t = Mode::Executable)]
+    pub mode: Mode,
 
     /// Verbose output
     #[clap(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// Only log errors and hide the progress bar. Overrides --verbose
+    #[clap(short, long)]
+    pub quiet: bool,
+
     /// Executable name
     #[clap(short = 'o', long, default_value = "main")]
     pub executable_name: String,
 
-    /// Execute IR with JIT
+    /// Parse the file as infix notation instead of RPN
+    #[clap(long)]
+    pub infix: bool,
+
+    /// Target triple to compile for (defaults to the host)
+    #[clap(long)]
+    pub target: Option<String>,
+
+    /// Target CPU, or `native` for the host CPU
+    #[clap(long, default_value = "generic")]
+    pub cpu: String,
+
+    /// Target features (e.g. `+avx2,+fma`), added to the host's with --cpu native
+    #[clap(long, default_value = "")]
+    pub features: String,
+
+    /// Write the compiled module in another format instead. Only with --mode object or executable
+    #[clap(long, value_enum)]
+    pub emit: Option<Emit>,
+
+    /// Keep the object files linked into the executable instead of deleting them
+    #[clap(long)]
+    pub keep_temps: bool,
+
+    /// Write each function to its own object file (`<EXECUTABLE_NAME>-<FUNCTION>.o` with --mode object)
+    #[clap(long)]
+    pub separate_objects: bool,
+
+    /// Start the program in this function. The top level may then only define functions
+    #[clap(long)]
+    pub entry: Option<String>,
+
+    /// Reject programs that use comparison results as numbers (e.g. `+ > 2 1 5`)
+    #[clap(long)]
+    pub no_bool_coercion: bool,
+
+    /// Reject an `if` without an `else` used as a value, instead of it evaluating to 0 when false
+    #[clap(long)]
+    pub no_implicit_else: bool,
+
+    /// Seed `rand` and `randint`, so every run draws the same numbers
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Reject programs that can give a different result on each run: `clock`, and `rand` without --seed
+    #[clap(long)]
+    pub deterministic: bool,
+
+    /// When interpreting, print the value of each top-level statement
+    #[clap(long)]
+    pub print_all: bool,
+
+    /// When interpreting, print numbers as their raw IEEE 754 bits in hex (e.g. 0x3ff0000000000000 for 1)
+    #[clap(long)]
+    pub print_bits: bool,
+
+    /// Print the parsed AST and exit
+    #[clap(long)]
+    pub ast: bool,
+
+    /// Print the defined functions with their parameters and exit
+    #[clap(long)]
+    pub dump_symbols: bool,
+
+    /// Write the parsed AST as a Graphviz DOT graph to this file and exit
+    #[clap(long, value_name = "DOT_FILE")]
+    pub graph: Option<String>,
+
+    /// Print the file formatted canonically and exit. Only RPN source can be formatted
+    #[clap(long)]
+    pub format: bool,
+
+    /// With --format, rewrite the file in place instead of printing it. Files with comments are left alone,
+    /// as formatting drops comments
+    #[clap(long, requires = "format", conflicts_with = "eval")]
+    pub write: bool,
+
+    /// Print the version of the linked LLVM and whether it can JIT on this host, then exit
     #[clap(long)]
-    pub jit: bool,
+    pub backend_info: bool,
 }
@@ -0,0 +1,131 @@
+use std::fmt;
+
+use laspa_std::runtime;
+
+use crate::RuntimeError;
+
+/// A function built into the language. Builtins are called like operators, with a fixed number of
+/// operands after the name (e.g. `ipow 2 16`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Builtin {
+    /// Integer power by repeated squaring (e.g. `ipow 2 16` is `65536`). The exponent must be an integer.
+    IPow,
+    /// Square root (e.g. `sqrt 16` is `4`).
+    Sqrt,
+    /// The sign of a number: `-1`, `0` or `1`. Both zeros give `0`.
+    Sign,
+    /// Round to the nearest integer, with halves away from zero (e.g. `round 2.5` is `3`, `round -2.5` is `-3`).
+    Round,
+    /// Round towards zero (e.g. `trunc -2.9` is `-2`).
+    Trunc,
+    /// A uniform random number in `[0, 1)`. Takes no operands. See [`CompileConfig::seed`](crate::CompileConfig::seed).
+    Rand,
+    /// A uniform random integer between two bounds, both included (e.g. `randint 1 6` rolls a die).
+    RandInt,
+    /// Seconds since an arbitrary point, from a monotonic clock. Takes no operands.
+    Clock,
+    /// The length of the hypotenuse with two sides (e.g. `hypot 3 4` is `5`), without overflow in the squares.
+    Hypot,
+    /// The distance between two points given as `x1 y1 x2 y2` (e.g. `dist 1 1 4 5` is `5`).
+    Dist,
+}
+
+impl Builtin {
+    /// Look up a builtin by name. Returns `None` if the name is not a builtin.
+    pub fn new(s: &str) -> Option<Self> {
+        match s {
+            "ipow" => Some(Self::IPow),
+            "sqrt" => Some(Self::Sqrt),
+            "sign" => Some(Self::Sign),
+            "round" => Some(Self::Round),
+            "trunc" => Some(Self::Trunc),
+            "rand" => Some(Self::Rand),
+            "randint" => Some(Self::RandInt),
+            "clock" => Some(Self::Clock),
+            "hypot" => Some(Self::Hypot),
+            "dist" => Some(Self::Dist),
+            _ => None,
+        }
+    }
+
+    /// The number of operands the builtin takes.
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Rand | Self::Clock => 0,
+            Self::IPow | Self::RandInt | Self::Hypot => 2,
+            Self::Dist => 4,
+            Self::Sqrt | Self::Sign | Self::Round | Self::Trunc => 1,
+        }
+    }
+
+    /// The name of the builtin in source.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IPow => "ipow",
+            Self::Sqrt => "sqrt",
+            Self::Sign => "sign",
+            Self::Round => "round",
+            Self::Trunc => "trunc",
+            Self::Rand => "rand",
+            Self::RandInt => "randint",
+            Self::Clock => "clock",
+            Self::Hypot => "hypot",
+            Self::Dist => "dist",
+        }
+    }
+
+    /// Evaluate the builtin on already evaluated operands.
+    pub fn eval(&self, args: &[f64]) -> Result<f64, RuntimeError> {
+        match self {
+            Self::IPow => ipow(args[0], args[1]).ok_or(RuntimeError::InvalidArgument {
+                builtin: *self,
+                value: args[1],
+            }),
+            Self::Sqrt => Ok(args[0].sqrt()),
+            // `signum` gives `1` for `0` and `-1` for `-0`
+            Self::Sign if args[0] == 0.0 => Ok(0.0),
+            Self::Sign => Ok(args[0].signum()),
+            Self::Round => Ok(args[0].round()),
+            Self::Trunc => Ok(args[0].trunc()),
+            // the generator is shared with compiled programs, so a seed draws the same numbers in both
+            Self::Rand => Ok(runtime::laspa_rand()),
+            Self::RandInt => match runtime::laspa_randint(args[0], args[1]) {
+                value if value.is_nan() => Err(RuntimeError::InvalidArgument {
+                    builtin: *self,
+                    value: args[1],
+                }),
+                value => Ok(value),
+            },
+            Self::Clock => Ok(runtime::laspa_clock()),
+            Self::Hypot => Ok(args[0].hypot(args[1])),
+            Self::Dist => Ok((args[2] - args[0]).hypot(args[3] - args[1])),
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Raise `base` to an integer power by repeated squaring. Returns `None` if `exp` is not an integer.
+fn ipow(base: f64, exp: f64) -> Option<f64> {
+    if exp.fract() != 0.0 || !exp.is_finite() {
+        return None;
+    }
+
+    let mut result = 1.0;
+    let mut base = base;
+    let mut n = exp.abs() as u64;
+    while n > 0 {
+        if n & 1 == 1 {
+            result *= base;
+        }
+        base *= base;
+        n >>= 1;
+    }
+
+    Some(if exp < 0.0 { 1.0 / result } else { result })
+}
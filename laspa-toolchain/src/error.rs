@@ -0,0 +1,185 @@
+use std::{error::Error, fmt};
+
+use crate::{Builtin, Type};
+
+/// An error produced while parsing source into an AST.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    /// A statement ended before an expression was complete (e.g. `+ 1`).
+    UnexpectedEnd,
+    /// A keyword that must be followed by a name was not (e.g. `let` on its own).
+    MissingName(String),
+    /// Function parameters or arguments that are not in the form `(arg1 arg2 ...)`.
+    InvalidArgs(String),
+    /// A function with two parameters of the same name (e.g. `fn f (x x)`).
+    DuplicateParam(String),
+    /// A parameter without a default after one with a default (e.g. `fn f (x=1 y)`).
+    MissingDefault(String),
+    /// An unknown type in a return type annotation.
+    InvalidType(String),
+    /// An unknown function attribute (e.g. `#[optimize fast]`).
+    InvalidAttribute(String),
+    /// A `#[memo]` function with a reference parameter or a `bool` return type.
+    InvalidMemo(String),
+    /// A `do` block with statements after it that was not closed on the same line.
+    UnterminatedBlock,
+    /// A token that is not valid at its position, or a name that is not a letter or `_` followed by letters,
+    /// digits and `_`.
+    UnexpectedToken(String),
+    /// A keyword, literal, operator or builtin used as the name of a variable or function (e.g. `let if 5`).
+    ReservedName(String),
+    /// A malformed number literal.
+    InvalidNumber(String),
+    /// A string that is not an operator.
+    InvalidOperator(String),
+    /// A statement outside a function in a program with an entry function.
+    TopLevelStatement,
+    /// An entry function that is not defined or takes parameters.
+    InvalidEntry(String),
+    /// A comparison result used as an operand of an operator or builtin when implicit bool coercion is off.
+    BoolAsNumber(String),
+    /// An `if` without an `else` used as an operand when implicit else is off.
+    IfWithoutElse,
+    /// Blocks nested deeper than [`MAX_BLOCK_DEPTH`](crate::MAX_BLOCK_DEPTH).
+    TooDeep,
+    /// An expression nested deeper than [`MAX_EXPRESSION_DEPTH`](crate::MAX_EXPRESSION_DEPTH).
+    ExpressionTooDeep,
+    /// A builtin whose value can change from run to run (`clock`, or `rand` without a seed) when
+    /// deterministic mode is on.
+    NonDeterministic(Builtin),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "Unexpected end of statement; expected an expression"),
+            Self::MissingName(keyword) => write!(f, "Expected a name after `{keyword}`"),
+            Self::InvalidArgs(args) => write!(
+                f,
+                "Invalid function arguments `{args}`. Must be in the form (arg1 arg2 ...)"
+            ),
+            Self::DuplicateParam(name) => write!(f, "Duplicate parameter `{name}`"),
+            Self::MissingDefault(name) => write!(
+                f,
+                "Parameter `{name}` must have a default, because a parameter before it has one"
+            ),
+            Self::InvalidType(ty) => write!(f, "Invalid type `{ty}`. Expected `num` or `bool`"),
+            Self::InvalidAttribute(attr) => write!(
+                f,
+                "Invalid attribute `{attr}`. Expected `#[memo]`, `#[optimize none]` or `#[optimize aggressive]`"
+            ),
+            Self::InvalidMemo(name) => write!(
+                f,
+                "Function `{name}` cannot be memoized: it must return a number and take no reference parameters"
+            ),
+            Self::UnterminatedBlock => write!(
+                f,
+                "Unterminated do block. Multi-line blocks must start with `do` on its own line"
+            ),
+            Self::UnexpectedToken(token) => write!(f, "Unexpected token `{token}`"),
+            Self::ReservedName(name) => {
+                write!(f, "`{name}` is reserved and cannot be used as a name")
+            }
+            Self::InvalidNumber(number) => write!(f, "Invalid number: {number}"),
+            Self::InvalidOperator(op) => write!(f, "Invalid operator `{op}`"),
+            Self::TopLevelStatement => write!(
+                f,
+                "Only function definitions are allowed at the top level with an entry function"
+            ),
+            Self::BoolAsNumber(op) => write!(
+                f,
+                "Comparison result used as a number in `{op}`; implicit bool coercion is off"
+            ),
+            Self::IfWithoutElse => write!(
+                f,
+                "An `if` used as a value must have an `else`; implicit else is off"
+            ),
+            Self::InvalidEntry(name) => write!(
+                f,
+                "Entry function `{name}` must be defined and take no parameters"
+            ),
+            Self::NonDeterministic(builtin) => write!(
+                f,
+                "`{builtin}` can give a different value on each run; deterministic mode is on"
+            ),
+            Self::TooDeep => write!(
+                f,
+                "Blocks are nested more than {} deep",
+                crate::MAX_BLOCK_DEPTH
+            ),
+            Self::ExpressionTooDeep => write!(
+                f,
+                "Expressions are nested more than {} deep",
+                crate::MAX_EXPRESSION_DEPTH
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// An error produced by the interpreter while evaluating an AST.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeError {
+    /// A variable was read or mutated before it was bound.
+    UndefinedVariable(String),
+    /// A function was called before it was defined.
+    UndefinedFunction(String),
+    /// A variable holding a number was called like a function (e.g. `let f 5; f (1)`).
+    NotCallable(String),
+    /// A function parameter that is not a plain name.
+    InvalidParameter(String),
+    /// A function returned a value that does not match its annotated return type.
+    ReturnTypeMismatch { function: String, expected: Type },
+    /// An argument for a reference parameter that is not a variable (e.g. `inc (1)` for `fn inc (&x)`).
+    InvalidReference(String),
+    /// A function reference used as a number (e.g. an AST holding `+ sum 1`).
+    FunctionAsValue(String),
+    /// A statement without a value used as an operand of an operator or builtin (e.g. `+ print 1 2`).
+    UnitOperand(String),
+    /// An operand a builtin cannot accept (e.g. a non-integer exponent for `ipow`).
+    InvalidArgument { builtin: Builtin, value: f64 },
+    /// The evaluation took more steps than it was allowed. Only folding constant calls limits the steps.
+    StepLimit,
+    /// Printed output could not be written (e.g. stdout was closed).
+    Output(String),
+    /// The interpreter panicked, which is a bug in laspa. Only returned by [`crate::Interpreter::from_ast_safe`].
+    InternalPanic(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVariable(name) => write!(f, "Variable not found: {name}"),
+            Self::UndefinedFunction(name) => write!(f, "Function not found: {name}"),
+            Self::NotCallable(name) => write!(f, "'{name}' is not a function"),
+            Self::InvalidParameter(name) => write!(f, "Invalid parameter in function '{name}'"),
+            Self::ReturnTypeMismatch { function, expected } => {
+                write!(f, "Function '{function}' must return a {expected}")
+            }
+            Self::InvalidReference(name) => {
+                write!(
+                    f,
+                    "Argument for reference parameter '{name}' must be a variable"
+                )
+            }
+            Self::FunctionAsValue(name) => {
+                write!(f, "Function '{name}' cannot be used as a number")
+            }
+            Self::UnitOperand(op) => {
+                write!(
+                    f,
+                    "A statement without a value cannot be an operand of '{op}'"
+                )
+            }
+            Self::InvalidArgument { builtin, value } => {
+                write!(f, "Invalid argument {value} for builtin '{builtin}'")
+            }
+            Self::StepLimit => write!(f, "Evaluation took too many steps"),
+            Self::Output(message) => write!(f, "Error writing output: {message}"),
+            Self::InternalPanic(message) => write!(f, "Internal error: {message}"),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
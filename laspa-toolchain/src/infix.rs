@@ -0,0 +1,462 @@
+/*!
+An alternative infix frontend for läspa. Expressions are written with the operator between the operands
+and follow the usual precedence rules (`*`, `/`, `%`, `mod` bind tighter than `+`, `-`, which bind tighter than
+comparisons, which bind tighter than `and`, then `or`), so `1 + 2 * 3` is `7`. Parentheses can be used for grouping.
+
+The parser produces the same [`Node`] AST as the RPN frontend, so the interpreter and LLVM backends are reused.
+
+```ignore
+fn collatz(n)
+    while n > 1
+        if n % 2 == 0
+            n := n / 2
+        else
+            n := 3 * n + 1
+        end
+    end
+    return n
+end
+
+let x = 10
+return collatz(x)
+```
+ */
+
+use std::{fmt, rc::Rc};
+
+use crate::{
+    check_params, is_reserved, BinaryExpr, BindExpr, Builtin, BuiltinExpr, FnCallExpr, FnExpr,
+    IfExpr, MutateExpr, Node, Number, Op, ParseError, PrintStdoutExpr, ReturnExpr, Type, UnaryExpr,
+    UnaryOp, WhileExpr, MAX_BLOCK_DEPTH, MAX_EXPRESSION_DEPTH,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(Op),
+    /// `:=`
+    Assign,
+    /// `=`
+    Equals,
+    LParen,
+    RParen,
+    Comma,
+    Colon,
+    /// A newline or `;`
+    Separator,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Ident(name) => write!(f, "{name}"),
+            Self::Op(op) => write!(f, "{op}"),
+            Self::Assign => write!(f, ":="),
+            Self::Equals => write!(f, "="),
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Comma => write!(f, ","),
+            Self::Colon => write!(f, ":"),
+            Self::Separator => write!(f, "end of line"),
+        }
+    }
+}
+
+/// Split infix source into tokens. Comments (`//`) are skipped.
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' | ';' => {
+                chars.next();
+                tokens.push(Token::Separator);
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                let n = number.parse::<Number>()?;
+                tokens.push(Token::Number(n.0));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                // operators spelled as words (e.g. `mod`)
+                match Op::new(&ident) {
+                    Ok(op) => tokens.push(Token::Op(op)),
+                    Err(_) => tokens.push(Token::Ident(ident)),
+                }
+            }
+            _ => {
+                chars.next();
+                let token = match (c, chars.peek()) {
+                    ('/', Some('/')) => {
+                        while chars.next_if(|&c| c != '\n').is_some() {}
+                        continue;
+                    }
+                    ('=', Some('=')) => {
+                        chars.next();
+                        Token::Op(Op::Eqt)
+                    }
+                    (':', Some('=')) => {
+                        chars.next();
+                        Token::Assign
+                    }
+                    ('=', _) => Token::Equals,
+                    ('(', _) => Token::LParen,
+                    (')', _) => Token::RParen,
+                    (',', _) => Token::Comma,
+                    (':', _) => Token::Colon,
+                    // the other symbols are spelled like in RPN
+                    _ => match Op::new(&c.to_string()) {
+                        Ok(op) => Token::Op(op),
+                        Err(_) => return Err(ParseError::UnexpectedToken(c.to_string())),
+                    },
+                };
+                tokens.push(token);
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// The left and right binding power of a binary operator. Higher binds tighter.
+fn binding_power(op: &Op) -> (u8, u8) {
+    match op {
+        Op::Or => (1, 2),
+        Op::And => (3, 4),
+        Op::Eqt => (5, 6),
+        Op::Gt | Op::Lt => (7, 8),
+        Op::Add | Op::Sub => (9, 10),
+        Op::Mul | Op::Div | Op::Mod | Op::EuclidMod => (11, 12),
+    }
+}
+
+/// The binding power of unary minus.
+const PREFIX_BP: u8 = 13;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// The number of blocks the parser is in.
+    blocks: usize,
+    /// The number of expressions the parser is in.
+    exprs: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            Some(Token::Ident(name)) if is_reserved(&name) => Err(ParseError::ReservedName(name)),
+            Some(Token::Ident(name)) => Ok(name),
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn peek_keyword(&self, keywords: &[&str]) -> Option<String> {
+        match self.peek() {
+            Some(Token::Ident(name)) if keywords.contains(&name.as_str()) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.peek() == Some(&Token::Separator) {
+            self.pos += 1;
+        }
+    }
+
+    /// Parse statements until one of `terminators` (consumed and returned) or the end of input.
+    fn block(&mut self, terminators: &[&str]) -> Result<(Vec<Node>, Option<String>), ParseError> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek().is_none() {
+                return Ok((nodes, None));
+            }
+            if let Some(keyword) = self.peek_keyword(terminators) {
+                self.pos += 1;
+                return Ok((nodes, Some(keyword)));
+            }
+            nodes.push(self.statement()?);
+        }
+    }
+
+    /// Parse a block nested in the current one, up to [`MAX_BLOCK_DEPTH`] deep.
+    fn nested_block(
+        &mut self,
+        terminators: &[&str],
+    ) -> Result<(Vec<Node>, Option<String>), ParseError> {
+        if self.blocks == MAX_BLOCK_DEPTH {
+            return Err(ParseError::TooDeep);
+        }
+        self.blocks += 1;
+        let block = self.block(terminators);
+        self.blocks -= 1;
+        block
+    }
+
+    /// Parse a block that must be closed with `end`.
+    fn body(&mut self) -> Result<Rc<[Node]>, ParseError> {
+        match self.nested_block(&["end"])? {
+            (body, Some(_)) => Ok(body.into()),
+            (_, None) => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Node, ParseError> {
+        let keyword = self.peek_keyword(&[
+            "let", "global", "return", "print", "while", "if", "fn", "do",
+        ]);
+        if keyword.is_some() {
+            self.pos += 1;
+        }
+
+        // every nested block recurses through here, so statements are parsed in their own functions to keep
+        // this stack frame small
+        match keyword.as_deref() {
+            Some("while") => self.while_statement(),
+            Some("if") => self.if_statement(),
+            Some("fn") => self.fn_statement(),
+            Some("do") => Ok(Node::Block(self.body()?)),
+            keyword => self.simple_statement(keyword),
+        }
+    }
+
+    /// Parse a statement that does not open a block, after its `keyword` (if any).
+    fn simple_statement(&mut self, keyword: Option<&str>) -> Result<Node, ParseError> {
+        let node = match keyword {
+            Some("let") => {
+                let name = self.ident()?;
+                let value = if self.peek() == Some(&Token::Equals) {
+                    self.pos += 1;
+                    self.expr(0)?
+                } else {
+                    Node::Number(Number(0.0))
+                };
+                Node::BindExpr(BindExpr {
+                    name,
+                    value: vec![value],
+                })
+            }
+            Some("global") => {
+                let name = self.ident()?;
+                self.expect(Token::Equals)?;
+                Node::GlobalExpr(BindExpr {
+                    name,
+                    value: vec![self.expr(0)?],
+                })
+            }
+            Some("return") => Node::ReturnExpr(ReturnExpr {
+                value: vec![self.expr(0)?],
+            }),
+            Some("print") => Node::PrintStdoutExpr(PrintStdoutExpr {
+                value: vec![self.expr(0)?],
+            }),
+            _ => match (self.peek(), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Ident(_)), Some(Token::Assign)) => {
+                    let name = self.ident()?;
+                    self.pos += 1;
+                    Node::MutateExpr(MutateExpr {
+                        name,
+                        value: vec![self.expr(0)?],
+                    })
+                }
+                _ => self.expr(0)?,
+            },
+        };
+
+        Ok(node)
+    }
+
+    fn while_statement(&mut self) -> Result<Node, ParseError> {
+        Ok(Node::WhileExpr(WhileExpr {
+            condition: vec![self.expr(0)?],
+            body: self.body()?,
+        }))
+    }
+
+    fn if_statement(&mut self) -> Result<Node, ParseError> {
+        let condition = vec![self.expr(0)?];
+        let (body, else_body) = match self.nested_block(&["else", "end"])? {
+            (body, Some(keyword)) if keyword == "else" => (body.into(), self.body()?),
+            (body, Some(_)) => (body.into(), Rc::from([])),
+            (_, None) => return Err(ParseError::UnexpectedEnd),
+        };
+        Ok(Node::IfExpr(IfExpr {
+            condition,
+            body,
+            else_body,
+        }))
+    }
+
+    fn fn_statement(&mut self) -> Result<Node, ParseError> {
+        let name = self.ident()?;
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        while self.peek() != Some(&Token::RParen) {
+            args.push(Node::Variable(self.ident()?));
+            if self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+            }
+        }
+        self.expect(Token::RParen)?;
+        check_params(&args)?;
+        let return_type = if self.peek() == Some(&Token::Colon) {
+            self.pos += 1;
+            Some(Type::new(&self.ident()?)?)
+        } else {
+            None
+        };
+        Ok(Node::FnExpr(FnExpr {
+            name,
+            args,
+            body: self.body()?,
+            return_type,
+            optimize: None,
+            memo: false,
+        }))
+    }
+
+    /// Parse an expression with a Pratt parser. Operators binding looser than `min_bp` are left to the caller.
+    fn expr(&mut self, min_bp: u8) -> Result<Node, ParseError> {
+        self.nested_expr(min_bp).map(|(node, _)| node)
+    }
+
+    /// [`expr`](Self::expr), also returning how deeply operators are nested in the expression. Both that and
+    /// the parser's own recursion are kept within [`MAX_EXPRESSION_DEPTH`].
+    fn nested_expr(&mut self, min_bp: u8) -> Result<(Node, usize), ParseError> {
+        if self.exprs == MAX_EXPRESSION_DEPTH {
+            return Err(ParseError::ExpressionTooDeep);
+        }
+        self.exprs += 1;
+
+        let (mut lhs, mut depth) = match self.next() {
+            Some(Token::Number(n)) => (Node::Number(Number(n)), 0),
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                let mut depth = 0;
+                while self.peek() != Some(&Token::RParen) {
+                    let (arg, arg_depth) = self.nested_expr(0)?;
+                    args.push(arg);
+                    depth = depth.max(arg_depth + 1);
+                    match self.peek() {
+                        Some(Token::Comma) => self.pos += 1,
+                        Some(Token::RParen) => (),
+                        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                        None => return Err(ParseError::UnexpectedEnd),
+                    }
+                }
+                self.expect(Token::RParen)?;
+                let call = match Builtin::new(&name) {
+                    Some(builtin) => {
+                        if args.len() != builtin.arity() {
+                            return Err(ParseError::InvalidArgs(name));
+                        }
+                        let args = args.into_iter().map(|arg| vec![arg]).collect();
+                        Node::BuiltinExpr(BuiltinExpr { builtin, args })
+                    }
+                    _ => Node::FnCallExpr(FnCallExpr { name, args }),
+                };
+                (call, depth)
+            }
+            Some(Token::Ident(name)) if name == "true" || name == "false" => {
+                (Node::BoolLit(name == "true"), 0)
+            }
+            Some(Token::Ident(name)) => (Node::Variable(name), 0),
+            Some(Token::LParen) => {
+                let inner = self.nested_expr(0)?;
+                self.expect(Token::RParen)?;
+                inner
+            }
+            Some(Token::Op(Op::Sub)) => {
+                let (operand, depth) = self.nested_expr(PREFIX_BP)?;
+                let neg = Node::UnaryExpr(UnaryExpr {
+                    op: UnaryOp::Neg,
+                    operand: vec![operand],
+                });
+                (neg, depth + 1)
+            }
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = op.clone();
+            let (l_bp, r_bp) = binding_power(&op);
+            if l_bp < min_bp {
+                break;
+            }
+            self.pos += 1;
+
+            // chains like `1 + 1 + 1` nest to the left without recursing here
+            let (rhs, rhs_depth) = self.nested_expr(r_bp)?;
+            depth = depth.max(rhs_depth) + 1;
+            if depth > MAX_EXPRESSION_DEPTH {
+                return Err(ParseError::ExpressionTooDeep);
+            }
+            lhs = Node::BinaryExpr(BinaryExpr {
+                op,
+                lhs: vec![lhs],
+                rhs: vec![rhs],
+            });
+        }
+
+        self.exprs -= 1;
+        Ok((lhs, depth))
+    }
+}
+
+/// Parse infix source into an AST. The result can be evaluated by any [`Compile`](crate::Compile) backend.
+pub fn parse_infix(source: &str) -> Result<Vec<Node>, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        blocks: 0,
+        exprs: 0,
+    };
+
+    match parser.block(&["end"])? {
+        (nodes, None) => Ok(nodes),
+        (_, Some(keyword)) => Err(ParseError::UnexpectedToken(keyword)),
+    }
+}
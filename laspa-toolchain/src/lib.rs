@@ -28,15 +28,112 @@ return collatz (x)
 ```
 
 ## Syntax
-The syntax is very simple. Each statement is separated by a newline or a semicolon. Comments are
-denoted by `//`. The language is **whitespace sensitive**, but indentation is **not** important. The language
-is also case sensitive. The language is also **RPN** (Reverse Polish Notation), so the operator
-comes after the operands. For example, `+ 1 2` would equal `3`.
+The syntax is very simple. Each statement is separated by a newline or a semicolon. Comments start with `//`
+and run to the end of the statement. The language is **whitespace sensitive**, but indentation is **not**
+important. The language is also case sensitive. Names start with a letter (in any script, e.g. `счёт` or `λ`)
+or `_`, followed by letters, digits and `_`. The language is also **RPN** (Reverse Polish Notation), so the
+operator comes after the operands. For example, `+ 1 2` would equal `3`. There is no precedence: each operand
+is the next complete expression, so `- 10 - 3 2` is `10 - (3 - 2)` and `/ / 100 2 5` is `(100 / 2) / 5`. A
+statement that starts with a keyword (e.g. `let`) also ends the statement before it, so `let x 5 let y 6` is
+two statements. A line that ends with `\` continues on the next line, so a long expression can be split over
+several lines.
+
+Without a `return`, a program evaluates to its last statement that produces a value. `print`, loops and
+function definitions do not produce one, so `let x 5; print x` evaluates to `5`, but a loop in which a
+`return` ran evaluates to its last iteration. A program in which no such statement ran (including one that is
+empty or only has comments) evaluates to `0`, or to [`Value::Unit`] with [`eval_value`]. A statement without a value cannot be an operand of an operator or builtin, so
+`+ print 1 2` is an error.
+
+`% a b` is the remainder of `a / b`, which has the sign of `a` (e.g. `% -7 3` is `-1`), while `mod a b` is
+never negative (e.g. `mod -7 3` is `2`).
+
+`neg x` negates `x`, and `not x` is `1` if `x` is `0` and `0` otherwise (e.g. `not == 1 2` is `1`).
+`and a b` is `1` if neither `a` nor `b` is `0`, and `or a b` is `1` if either is not, so conditions can be
+combined (e.g. `if and > x 0 < x 10`). Both operands are always evaluated.
+
+Comparisons evaluate to `1` or `0`, so they can be used as numbers (e.g. `+ > 2 1 5` is `6`). Turning off
+[`CompileConfig::implicit_bool_coercion`] (`--no-bool-coercion`) rejects such programs instead. Two
+comparison results can always be compared with `==`, which is true when both are true or both are false
+(e.g. `== > 3 1 < 1 3` is `1`).
+
+The operands of an operator are evaluated left to right by both the interpreter and the LLVM backend, so
+`- do print 1 1 end do print 2 2 end` prints `1` before `2`.
 
 ### Code Blocks and Functions
 Every body of code must end (loops, if statements, functions, etc.)
 with the keyword `end`. Every function must start with `fn` and end with `end`. The parameters of a function are in the form `(param1 param2 ...)`.
 
+A parameter can have a number as its default, e.g. `fn greet (name times=1)`. A call that leaves out trailing
+arguments (e.g. `greet (5)`) uses the defaults of their parameters, so parameters with a default must come last.
+
+A `for` loop counts a variable from a start value up to an exclusive bound, e.g. `for i 0 10`. An optional
+step follows the bound; a negative step counts down, e.g. `for i 10 0 -2`.
+
+A function can annotate its return type after its parameters, e.g. `fn isodd (n) : bool`. The supported
+types are `num` (the default) and `bool`. `true` and `false` are bool literals, so they cannot be used as names.
+
+The LLVM backend also accepts an optimization hint after the parameters. `fn hot (x) #[optimize aggressive]`
+runs a heavier pass pipeline on that function, and `#[optimize none]` leaves it unoptimized.
+
+The interpreter caches the results of a function marked `#[memo]` (e.g. `fn fib (n) #[memo]`) by its
+argument values, so a call with arguments seen before returns the earlier result without running the body.
+This is only correct for pure functions: the body must not print, mutate variables outside the function, or
+depend on anything but its parameters. Memoized functions must return a number and cannot take reference
+parameters.
+
+Functions are bound late: a call uses the most recent definition of the function that ran before it, so
+redefining `fn f` between two calls changes what the second call does.
+
+A function can be bound to another name with `let`: after `let f sum`, `f (1 2)` calls `sum`. The binding
+adds `f` to the functions rather than the variables, so `f` cannot be used as a number.
+
+A program can also start in a function: with [`CompileConfig::entry`] (or `--entry`) set to `start`, the
+top level may only define functions, and the program returns the result of calling `start ()`.
+
+Variables bound with `let` live in the current scope. Each function call gets its own scope, and names
+that are not found there are looked up in the global scope. `global x 10` binds `x` in the global scope
+regardless of where it is run, so a function can create or update a global. A `let` without a value
+(e.g. `let x`) binds the variable to `0`.
+
+A parameter written as `&x` is passed by reference: the argument must be a variable, and changes the
+function makes to `x` are seen by the caller (e.g. `fn inc (&x)` followed by `inc (y)` can update `y`).
+
+A loop used as an operand evaluates to the value of its body in the last iteration, or `0` if the body
+never ran, so `let total while < i n` followed by a body ending in `* i i` binds the last square. As a
+statement, a loop still has no value. The LLVM backend evaluates loops to `0`.
+
+Statements can be grouped into a single expression with `do ... end`. The block evaluates to the value
+of its last statement, so it can be used as an operand (e.g. `:= x do let t + a b + t 1 end`).
+
+In the interpreter, an `if` used as an operand evaluates to the value of the branch that ran, so after
+`let x if > a b` with `a` as its body, `x` is `a` if `a` is greater. An `if` without an `else` has no branch
+to run when its condition is false, and evaluates to `0`. Turning off [`CompileConfig::implicit_else`]
+(`--no-implicit-else`) rejects an `if` without an `else` used as an operand instead. The LLVM backend always
+evaluates an `if` to `0`.
+
+### Builtins
+Builtin functions are called like operators, with their operands after the name:
+- `ipow base exp` raises `base` to an integer power `exp` by repeated squaring (e.g. `ipow 2 16` is `65536`).
+  A non-integer exponent is an error in the interpreter and is truncated by the LLVM backend.
+- `sqrt x` is the square root of `x`. The LLVM backend calls `laspa_sqrt` from the runtime library
+  (`laspa-std`), which is linked into compiled executables.
+- `sign x` is `-1`, `0` or `1` depending on the sign of `x`. `sign -0` is `0`, and `sign` of NaN is NaN.
+- `round x` rounds to the nearest integer with halves away from zero, so `round 2.5` is `3` and
+  `round -2.5` is `-3` (not banker's rounding). `trunc x` rounds towards zero, so `trunc -2.9` is `-2`.
+- `rand` takes no operands and is a uniform random number in `[0, 1)`. `randint lo hi` is a uniform random
+  integer from `lo` to `hi`, both included; an empty range is an error in the interpreter and NaN in compiled
+  programs. Both backends use the xorshift generator in `laspa-std`, seeded from the clock unless
+  [`CompileConfig::seed`] (`--seed`) is set.
+- `clock` takes no operands and is the number of seconds since an arbitrary point, from a monotonic clock, so
+  `- clock start` times the code since `let start clock`. Since it and unseeded random numbers change from run
+  to run, [`CompileConfig::deterministic`] (`--deterministic`) rejects them with [`check_deterministic`].
+- `hypot a b` is `sqrt + * a a * b b` without overflow or underflow in the squares, so `hypot 3 4` is `5`.
+  `dist x1 y1 x2 y2` is the distance between two points, so `dist 1 1 4 5` is `5`. The LLVM backend calls
+  `laspa_hypot` and `laspa_dist` from the runtime library.
+
+Keywords, operators and builtins cannot be used as the name of a variable or function (e.g. `let sqrt 2` is
+[`ParseError::ReservedName`]).
+
 **Warning**: There is little error handling, so if you make a mistake, the program will panic, or the result will be incorrect.
 
 ## Usage
@@ -53,33 +150,90 @@ use laspa::{Interpreter, Compile, CompileConfig};
 
 let result = Interpreter::from_source("return + 1 2;", &CompileConfig::from(false, false));
 assert_eq!(result, 3.0);
+```
+
+## Testing
+[`run_expect`] runs a program with the [`Interpreter`] and panics if it fails or does not return the expected
+value, so läspa programs can be tested with the usual `#[test]` functions.
+
+```rust
+laspa::run_expect("let x 2; return * x 3", 6.0);
 ```
  */
 
+mod builtin;
+mod error;
+mod infix;
 mod llvm;
 
+pub use builtin::Builtin;
+pub use error::{ParseError, RuntimeError};
 use indicatif::ProgressBar;
+pub use infix::parse_infix;
 use lazy_static::lazy_static;
 use llvm::LogExpect;
+pub use llvm::{backend_info, compile_to_object, JitSession};
 use regex::{Regex, Split};
-use std::{collections::HashMap, str::SplitWhitespace};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    io::{BufWriter, Read, Write},
+    panic::AssertUnwindSafe,
+    rc::Rc,
+    str::{FromStr, SplitWhitespace},
+};
 
 /// The default number type. Every number is a [`f64`] number for simplicity.
+/// `==` compares the values exactly, which is what comparing two ASTs needs, so `Number(0.1 + 0.2)` is not
+/// equal to `Number(0.3)`. Use [`Number::approx_eq`] to compare computed values with a tolerance.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Number(pub f64);
 
 impl Number {
     /// Create a new number from a string. This will return an error if the string is not a valid
+    /// number. An integer literal outside the range of an [`i64`] (e.g. `99999999999999999999`) is not an
+    /// error: it is rounded to the nearest [`f64`] with a warning, since it cannot be kept exactly.
     pub fn new(s: &str) -> Result<Self, String> {
         match s.parse::<f64>() {
-            Ok(n) => Ok(Self(n)),
+            Ok(n) => {
+                if overflows_i64(s) {
+                    log::warn!(
+                        "Integer literal {s} does not fit in 64 bits and is rounded to {n:e}"
+                    );
+                }
+                Ok(Self(n))
+            }
             Err(_) => Err(format!("Invalid number: {s}")),
         }
     }
+
+    /// Whether the two numbers differ by at most `epsilon`. Equal infinities are approximately equal, and
+    /// NaN is not approximately equal to anything.
+    pub fn approx_eq(&self, other: &Number, epsilon: f64) -> bool {
+        self.0 == other.0 || (self.0 - other.0).abs() <= epsilon
+    }
+}
+
+/// Whether `s` is an integer literal (digits with an optional sign) that does not fit in an [`i64`].
+fn overflows_i64(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) && s.parse::<i64>().is_err()
+}
+
+impl FromStr for Number {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s).map_err(|_| ParseError::InvalidNumber(s.to_string()))
+    }
 }
 
 /// The default operator type. This is used for arithmetic and comparison operations.
+/// Comparisons are ordered: they are false if either operand is NaN, and `-0` equals `0`.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     Add,
     Sub,
@@ -89,39 +243,190 @@ pub enum Op {
     Gt,
     /// Less than
     Lt,
-    /// Modulo
+    /// Remainder, which has the sign of the dividend (e.g. `% -7 3` is `-1`)
     Mod,
     /// Equal to
     Eqt,
+    /// Euclidean modulo, which is never negative (e.g. `mod -7 3` is `2`)
+    EuclidMod,
+    /// Logical and: `1` if neither operand is `0`, otherwise `0`
+    And,
+    /// Logical or: `1` if either operand is not `0`, otherwise `0`
+    Or,
 }
 
 impl Op {
-    /// Create a new operator from a string. This will panic if the string is not a valid operator.
-    pub fn new(s: &str) -> Self {
+    /// Create a new operator from its source form (e.g. `"+"` or `"mod"`). An unknown operator is a
+    /// [`ParseError::InvalidOperator`], like with [`str::parse`].
+    pub fn new(s: &str) -> Result<Self, ParseError> {
+        s.parse()
+    }
+
+    /// Whether the operator is a comparison, which evaluates to `1` or `0`.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Self::Gt | Self::Lt | Self::Eqt)
+    }
+
+    /// Whether the operator combines conditions (`and`, `or`). It also evaluates to `1` or `0`.
+    pub fn is_logical(&self) -> bool {
+        matches!(self, Self::And | Self::Or)
+    }
+
+    /// The source form of the operator, which [`Op::new`] parses back (e.g. `"+"` or `"mod"`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Gt => ">",
+            Self::Lt => "<",
+            Self::Mod => "%",
+            Self::Eqt => "==",
+            Self::EuclidMod => "mod",
+            Self::And => "and",
+            Self::Or => "or",
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Op {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "+" => Self::Add,
-            "-" => Self::Sub,
-            "*" => Self::Mul,
-            "/" => Self::Div,
-            ">" => Self::Gt,
-            "<" => Self::Lt,
-            "%" => Self::Mod,
-            "==" => Self::Eqt,
-            _ => log_and_exit!("Invalid operator"),
+            "+" => Ok(Self::Add),
+            "-" => Ok(Self::Sub),
+            "*" => Ok(Self::Mul),
+            "/" => Ok(Self::Div),
+            ">" => Ok(Self::Gt),
+            "<" => Ok(Self::Lt),
+            "%" => Ok(Self::Mod),
+            "==" => Ok(Self::Eqt),
+            "mod" => Ok(Self::EuclidMod),
+            "and" => Ok(Self::And),
+            "or" => Ok(Self::Or),
+            _ => Err(ParseError::InvalidOperator(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<&str> for Op {
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The type of a value. Used to annotate the return type of a function (e.g. `fn isodd (n) : bool`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Type {
+    /// A [`Number`]
+    Num,
+    /// The result of a comparison. The interpreter represents it as `1` (true) or `0` (false).
+    Bool,
+}
+
+impl Type {
+    /// Create a new type from a string. This will return an error if the string is not a valid type.
+    pub fn new(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "num" => Ok(Self::Num),
+            "bool" => Ok(Self::Bool),
+            _ => Err(ParseError::InvalidType(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num => write!(f, "num"),
+            Self::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+/// An optimization hint for a function, given as an attribute after its parameters
+/// (e.g. `fn hot (x) #[optimize aggressive]`). Only the LLVM backend uses it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OptimizeHint {
+    /// Do not optimize the function.
+    None,
+    /// Run a heavier pass pipeline on the function.
+    Aggressive,
+}
+
+impl OptimizeHint {
+    /// Create a new hint from the contents of an attribute (e.g. `optimize aggressive`).
+    pub fn new(s: &str) -> Result<Self, ParseError> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("optimize"), Some("none"), None) => Ok(Self::None),
+            (Some("optimize"), Some("aggressive"), None) => Ok(Self::Aggressive),
+            _ => Err(ParseError::InvalidAttribute(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for OptimizeHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "optimize none"),
+            Self::Aggressive => write!(f, "optimize aggressive"),
         }
     }
 }
 
 /// The default binary expression type. This is used for arithmetic and comparison operations (e.g. `+ 1 2` would equal `3`).
+/// `lhs` is always evaluated before `rhs`.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinaryExpr {
     pub op: Op,
     pub lhs: Vec<Node>,
     pub rhs: Vec<Node>,
 }
 
+/// A unary operator (e.g. `neg` in `neg 5`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOp {
+    /// Negation (e.g. `neg 5` is `-5`)
+    Neg,
+    /// Logical not: `1` if the operand is `0`, otherwise `0` (e.g. `not == 1 2` is `1`)
+    Not,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Neg => write!(f, "neg"),
+            Self::Not => write!(f, "not"),
+        }
+    }
+}
+
+/// The default unary expression type. This is used for operators with one operand (e.g. `neg x`).
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnaryExpr {
+    pub op: UnaryOp,
+    pub operand: Vec<Node>,
+}
+
 /// The default bind expression type. This is used to bind a value to a variable (e.g. `let x 10` binding the number `10` to `x`).
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BindExpr {
     pub name: String,
     pub value: Vec<Node>,
@@ -129,6 +434,7 @@ pub struct BindExpr {
 
 /// The default return expression type. This is used to return a value from a function. If this is not used, the last value in the function will be returned.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReturnExpr {
     pub value: Vec<Node>,
 }
@@ -136,6 +442,7 @@ pub struct ReturnExpr {
 /// The default mutate expression type. This is used to mutate a variable (e.g. `:= x 10` setting the value of `x` to `10`).
 /// Variables can only be mutable.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MutateExpr {
     pub name: String,
     pub value: Vec<Node>,
@@ -143,59 +450,144 @@ pub struct MutateExpr {
 
 /// The default while expression type. This is used to create a while loop (e.g. `while < x 10` will loop while `x` is less than `10`).
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WhileExpr {
     pub condition: Vec<Node>,
-    pub body: Vec<Node>,
+    pub body: Rc<[Node]>,
+}
+
+/// The default for expression type. This is used to create a counting loop (e.g. `for i 0 10` will run the
+/// loop with `i` from `0` up to, but not including, `10`). An optional step can follow the bound
+/// (e.g. `for i 10 0 -2`); a negative step counts down while `i` is greater than the bound.
+///
+/// The loop variable is an [`f64`] in both backends, which holds every integer up to 2^53 exactly, so a loop
+/// with an integral start, bound and step never drifts. A fractional step does: `for x 0 1 0.1` adds up
+/// rounding errors and runs 11 times. Count with integers and scale instead (e.g. `for i 0 10` with
+/// `let x / i 10`).
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForExpr {
+    pub var: String,
+    pub start: Vec<Node>,
+    pub end: Vec<Node>,
+    pub step: Vec<Node>,
+    pub body: Rc<[Node]>,
 }
 
 /// The default if expression type. This is used to create an if statement (e.g. `if < x 10` will run the code in the if statement if `x` is less than `10`).
 /// The else statement is optional.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IfExpr {
     pub condition: Vec<Node>,
-    pub body: Vec<Node>,
-    pub else_body: Vec<Node>,
+    pub body: Rc<[Node]>,
+    pub else_body: Rc<[Node]>,
 }
 
 /// The default function expression type. This is used to create a function (e.g. `fn sum (x y);return + x y;end` will create a function called `sum` that takes two arguments, `x` and `y`, and returns the sum of the two).
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FnExpr {
     pub name: String,
+    /// The parameters: a [`Node::Variable`], a [`Node::Reference`] for `&x`, or a [`Node::BindExpr`] holding
+    /// the default of a parameter like `times=1`.
     pub args: Vec<Node>,
-    pub body: Vec<Node>,
+    /// Shared, so the copies in the AST and the function tables point at one body.
+    pub body: Rc<[Node]>,
+    /// The annotated return type, if any (e.g. `fn isodd (n) : bool`).
+    pub return_type: Option<Type>,
+    /// The optimization hint, if any (e.g. `fn hot (x) #[optimize aggressive]`).
+    pub optimize: Option<OptimizeHint>,
+    /// Whether the interpreter caches the results of the function by its arguments (`#[memo]`).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memo: bool,
 }
 
 /// The default function call expression type. This is used to call a function (e.g. `sum (1 2)` will call the function `sum` with the arguments `1` and `2`).
+/// An argument can be a whole expression, so `sum (+ 1 2 x)` passes `3` and `x`.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FnCallExpr {
     pub name: String,
     pub args: Vec<Node>,
 }
 
+/// The default builtin expression type. This is used to call a [`Builtin`] (e.g. `ipow 2 16` will raise `2` to the power of `16`).
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BuiltinExpr {
+    pub builtin: Builtin,
+    pub args: Vec<Vec<Node>>,
+}
+
 /// The default print expression type. This is used to print a value to stdout (e.g. `print 1` will print `1` to stdout).
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrintStdoutExpr {
     pub value: Vec<Node>,
 }
 
 /// The default node type. This is used to represent every element of the language. This is used to create an abstract syntax tree (AST).
+/// Block bodies (loops, if statements, functions and `do` blocks) are shared `Rc<[Node]>` slices, so cloning a node does not copy its body.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node {
     Number(Number),
     BinaryExpr(BinaryExpr),
+    UnaryExpr(UnaryExpr),
     BindExpr(BindExpr),
     Variable(String),
     ReturnExpr(ReturnExpr),
     MutateExpr(MutateExpr),
     WhileExpr(WhileExpr),
+    ForExpr(ForExpr),
     IfExpr(IfExpr),
     FnExpr(FnExpr),
     FnCallExpr(FnCallExpr),
+    BuiltinExpr(BuiltinExpr),
     PrintStdoutExpr(PrintStdoutExpr),
+    /// Bind a value in the global scope (e.g. `global x 10`), even from inside a function.
+    GlobalExpr(BindExpr),
+    /// A `do ... end` block. Evaluates to the value of its last statement that produces a value.
+    Block(Rc<[Node]>),
+    /// A parameter passed by reference (e.g. `&x` in `fn inc (&x)`). Evaluates like a variable.
+    Reference(String),
+    /// A reference to a function by name (e.g. `sum` in `let f sum`). Binding one makes the bound name
+    /// callable like the function (e.g. `f (1 2)`); it cannot be used as a number.
+    FnRef(String),
+    /// A `true` or `false` literal. It is a bool like a comparison result, so it is `1` or `0` as a number.
+    BoolLit(bool),
+}
+
+/// The value of a program: a number, a bool if it ends with one (e.g. `let b false`), or `Unit` if no
+/// statement in it produces a value (e.g. `print 5`).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Unit,
+}
+
+impl Node {
+    /// Whether the node produces a value. A body evaluates to the value of its last such node, and
+    /// `print`, loops and function definitions are skipped.
+    pub fn has_value(&self) -> bool {
+        !matches!(
+            self,
+            Node::PrintStdoutExpr(_) | Node::WhileExpr(_) | Node::ForExpr(_) | Node::FnExpr(_)
+        )
+    }
 }
 
 lazy_static! {
     static ref RE: Regex = Regex::new(r"[;\n]").log_expect("");
+    static ref CONTINUATION: Regex = Regex::new(r"\\[ \t]*\r?\n").log_expect("");
+}
+
+/// Join each line that ends with `\` with the next one, so a long statement can span several lines.
+/// [`parse_source`] and [`parse_recover`] do this before lexing.
+pub fn join_lines(source: &str) -> Cow<'_, str> {
+    CONTINUATION.replace_all(source, " ")
 }
 
 /// Lex a string into tokens. This will split the string into tokens, which can then be parsed into an AST.
@@ -204,9 +596,49 @@ pub fn lex(s: &str) -> regex::Split<'static, '_> {
 }
 
 /// Parse tokens into an AST. This will parse a string of tokens into an AST, which can then be evaluated.
+/// Returns the first [`ParseError`] if a statement is malformed; use [`parse_recover`] to get all of them.
 pub fn parse(
     tokens: &mut Split<'static, '_>,
     functions: &mut HashMap<String, FnExpr>,
+) -> Result<Vec<Node>, ParseError> {
+    let mut errors = Vec::new();
+    let nodes = parse_recovering(tokens, functions, &mut errors, 0);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(nodes),
+    }
+}
+
+/// Parse source (in RPN syntax) without stopping at the first error. A malformed statement is skipped up to
+/// the next `;` or newline and its error is collected, so every problem in the source can be reported at once.
+pub fn parse_recover(source: &str) -> (Vec<Node>, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let source = join_lines(source);
+    let nodes = parse_recovering(&mut lex(&source), &mut HashMap::new(), &mut errors, 0);
+    (nodes, errors)
+}
+
+/// Parse source (in RPN syntax) without panicking on any input, malformed or not. This is the entry point for
+/// fuzzing the parser (e.g. with `cargo fuzz`).
+pub fn try_parse(source: &str) -> Result<Vec<Node>, ParseError> {
+    parse_source(source, Syntax::Rpn)
+}
+
+/// How deeply blocks can be nested. Each block is parsed by a recursive call, so this keeps the native stack
+/// from overflowing.
+pub const MAX_BLOCK_DEPTH: usize = 256;
+
+/// How deeply operators can be nested in an expression (e.g. `neg neg 1` is 2 deep). Expressions are parsed
+/// without recursion, but checking, folding and compiling them recurses, so this bounds their stack use.
+pub const MAX_EXPRESSION_DEPTH: usize = 256;
+
+/// Parse tokens up to an `end` keyword, skipping malformed statements and collecting their errors. `depth` is
+/// the number of blocks the tokens are in.
+fn parse_recovering(
+    tokens: &mut Split<'static, '_>,
+    functions: &mut HashMap<String, FnExpr>,
+    errors: &mut Vec<ParseError>,
+    depth: usize,
 ) -> Vec<Node> {
     let mut nodes = Vec::new();
     while let Some(token) = tokens.next() {
@@ -215,273 +647,2257 @@ pub fn parse(
             break;
         }
 
-        if let Ok(mut new_nodes) = parse_sentence(&mut token.split_whitespace(), functions) {
-            nodes.append(&mut new_nodes);
+        if token.trim().is_empty() {
+            continue;
         }
 
-        if let Some(Node::WhileExpr(e)) = nodes.last_mut() {
-            if e.body.is_empty() {
-                e.body = parse(tokens, functions);
+        let mut new_nodes = match parse_sentence(&mut token.split_whitespace(), functions) {
+            Ok(new_nodes) => new_nodes,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        // blocks can also be opened in operand position (e.g. `let x do`)
+        if let Some(node) = new_nodes.last_mut().and_then(pending_block) {
+            if depth == MAX_BLOCK_DEPTH {
+                // the rest of the source cannot be matched up with its blocks
+                errors.push(ParseError::TooDeep);
+                tokens.for_each(drop);
+                break;
             }
+            parse_block(node, tokens, functions, errors, depth + 1);
         }
+        nodes.append(&mut new_nodes);
+        // println!("nodes: {:?}", nodes)
+    }
+    nodes
+}
+
+/// Find the innermost block (loop, if, function, etc.) in a node that is still waiting for its body.
+fn pending_block(node: &mut Node) -> Option<&mut Node> {
+    let is_pending = match node {
+        Node::WhileExpr(e) => e.body.is_empty(),
+        Node::ForExpr(e) => e.body.is_empty(),
+        Node::IfExpr(e) => e.body.is_empty(),
+        Node::FnExpr(e) => e.body.is_empty(),
+        Node::Block(body) => body.is_empty(),
+        _ => false,
+    };
+
+    if is_pending {
+        return Some(node);
+    }
 
-        if let Some(Node::IfExpr(e)) = nodes.last_mut() {
-            if e.body.is_empty() {
-                let body = parse(tokens, functions);
-                let mut body = body.split(|n| n == &Node::Variable("else".to_string()));
-                e.body = body.next().log_expect("").to_vec();
-                e.else_body = body.next().unwrap_or(&Vec::new()).to_vec();
+    let operand = match node {
+        Node::BinaryExpr(e) => &mut e.rhs,
+        Node::UnaryExpr(e) => &mut e.operand,
+        Node::BindExpr(e) | Node::GlobalExpr(e) => &mut e.value,
+        Node::ReturnExpr(e) => &mut e.value,
+        Node::MutateExpr(e) => &mut e.value,
+        Node::PrintStdoutExpr(e) => &mut e.value,
+        Node::BuiltinExpr(e) => e.args.last_mut()?,
+        _ => return None,
+    };
+
+    operand.last_mut().and_then(pending_block)
+}
+
+/// Parse the body of a block up to its `end` keyword.
+fn parse_block(
+    node: &mut Node,
+    tokens: &mut Split<'static, '_>,
+    functions: &mut HashMap<String, FnExpr>,
+    errors: &mut Vec<ParseError>,
+    depth: usize,
+) {
+    match node {
+        Node::WhileExpr(e) => e.body = parse_recovering(tokens, functions, errors, depth).into(),
+        Node::ForExpr(e) => e.body = parse_recovering(tokens, functions, errors, depth).into(),
+        Node::IfExpr(e) => {
+            let mut body = parse_recovering(tokens, functions, errors, depth);
+            let else_pos = body
+                .iter()
+                .position(|n| n == &Node::Variable("else".to_string()));
+            if let Some(i) = else_pos {
+                e.else_body = body.split_off(i + 1).into();
+                body.pop();
             }
+            e.body = body.into();
+        }
+        Node::FnExpr(e) => {
+            e.body = parse_recovering(tokens, functions, errors, depth).into();
+            // keep the finished definition so the table can be reused (e.g. with `save_functions`)
+            functions.insert(e.name.clone(), e.clone());
+        }
+        Node::Block(body) => *body = parse_recovering(tokens, functions, errors, depth).into(),
+        _ => (),
+    }
+}
+
+/// Keywords that start a statement, so they also end the statement before them on the same line.
+const STATEMENT_KEYWORDS: [&str; 9] = [
+    "let", "global", "return", ":=", "while", "for", "if", "fn", "print",
+];
+
+/// Words with a meaning of their own, which cannot be used as the name of a variable or function.
+const RESERVED_WORDS: [&str; 17] = [
+    "let", "global", "return", ":=", "while", "for", "if", "fn", "print", "end", "else", "do",
+    "neg", "not", "true", "false", "//",
+];
+
+/// Whether `name` is a keyword, literal, operator or builtin, and so cannot be bound or defined.
+pub(crate) fn is_reserved(name: &str) -> bool {
+    RESERVED_WORDS.contains(&name) || Op::new(name).is_ok() || Builtin::new(name).is_some()
+}
+
+/// Whether `name` can name a variable or function: a letter (in any script) or `_`, followed by letters, digits
+/// and `_`, that does not also read as a number (e.g. `inf`). This matches the identifiers of the infix frontend.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && name.parse::<f64>().is_err()
+}
+
+/// An expression in a sentence that is still waiting for some of its operands.
+enum Partial {
+    Binary(Op),
+    Unary(UnaryOp),
+    Bind(String),
+    Global(String),
+    Return,
+    Mutate(String),
+    While,
+    For(String),
+    If,
+    Print,
+    Block,
+    Builtin(Builtin),
+}
+
+/// The result of parsing a single token of a sentence.
+enum Parsed {
+    /// An expression that still needs its operands.
+    Partial(Partial),
+    /// A finished expression (empty for a comment).
+    Done(Vec<Node>),
+}
+
+/// A [`Partial`] expression and the operands parsed for it so far.
+struct Frame {
+    partial: Partial,
+    operands: Vec<Vec<Node>>,
+}
+
+impl Frame {
+    fn new(partial: Partial) -> Self {
+        Self {
+            partial,
+            operands: Vec::new(),
         }
+    }
+
+    /// Whether the expression takes another operand. `tokens` is peeked for expressions with optional operands.
+    fn wants_operand(&self, tokens: &mut SplitWhitespace) -> Result<bool, ParseError> {
+        let n = self.operands.len();
+        Ok(match &self.partial {
+            Partial::Binary(_) => n < 2,
+            Partial::For(_) => n < 2 || (n == 2 && tokens.clone().next().is_some()),
+            Partial::Builtin(builtin) => n < builtin.arity(),
+            // a block on a single line is parsed here, otherwise the body follows on the next lines
+            Partial::Block => match tokens.clone().next() {
+                Some("end") => {
+                    tokens.next();
+                    false
+                }
+                Some(_) => true,
+                None if self.operands.iter().all(Vec::is_empty) => false,
+                None => return Err(ParseError::UnterminatedBlock),
+            },
+            _ => n < 1,
+        })
+    }
 
-        if let Some(Node::FnExpr(e)) = nodes.last_mut() {
-            if e.body.is_empty() {
-                e.body = parse(tokens, functions);
+    fn finish(self) -> Node {
+        let mut operands = self.operands.into_iter();
+        let mut next = || operands.next().unwrap_or_default();
+        match self.partial {
+            Partial::Unary(op) => Node::UnaryExpr(UnaryExpr {
+                op,
+                operand: next(),
+            }),
+            Partial::Binary(op) => Node::BinaryExpr(BinaryExpr {
+                op,
+                lhs: next(),
+                rhs: next(),
+            }),
+            Partial::Bind(name) => Node::BindExpr(BindExpr {
+                name,
+                value: next(),
+            }),
+            Partial::Global(name) => Node::GlobalExpr(BindExpr {
+                name,
+                value: next(),
+            }),
+            Partial::Return => Node::ReturnExpr(ReturnExpr { value: next() }),
+            Partial::Mutate(name) => Node::MutateExpr(MutateExpr {
+                name,
+                value: next(),
+            }),
+            Partial::While => Node::WhileExpr(WhileExpr {
+                condition: next(),
+                body: Rc::from([]),
+            }),
+            Partial::For(var) => {
+                let (start, end) = (next(), next());
+                let step = operands
+                    .next()
+                    .unwrap_or_else(|| vec![Node::Number(Number(1.0))]);
+                Node::ForExpr(ForExpr {
+                    var,
+                    start,
+                    end,
+                    step,
+                    body: Rc::from([]),
+                })
             }
+            Partial::If => Node::IfExpr(IfExpr {
+                condition: next(),
+                body: Rc::from([]),
+                else_body: Rc::from([]),
+            }),
+            Partial::Print => Node::PrintStdoutExpr(PrintStdoutExpr { value: next() }),
+            Partial::Block => Node::Block(operands.flatten().collect()),
+            Partial::Builtin(builtin) => Node::BuiltinExpr(BuiltinExpr {
+                builtin,
+                args: operands.collect(),
+            }),
         }
-        // println!("nodes: {:?}", nodes)
     }
-    nodes
 }
 
 /// Parse a sentence into an AST. This will parse a sentence into an AST, which can then be evaluated.
 /// Sentences are separated by newlines or `;` as provided by the regex in the lexer.
+/// Nested expressions are kept on an explicit stack, so deeply nested sentences do not overflow the native stack.
+/// A sentence can hold several statements if each one starts with a keyword (e.g. `let x 5 let y 6`).
 fn parse_sentence(
     tokens: &mut SplitWhitespace,
     functions: &mut HashMap<String, FnExpr>,
-) -> Result<Vec<Node>, String> {
-    let mut nodes = Vec::new();
-    match tokens.next() {
-        Some(t) => match t {
-            "+" | "-" | "*" | "/" | ">" | "<" | "%" | "==" => {
-                nodes.push(Node::BinaryExpr(BinaryExpr {
-                    op: Op::new(t),
-                    lhs: parse_sentence(tokens, functions).log_expect(""),
-                    rhs: parse_sentence(tokens, functions).log_expect(""),
-                }));
+) -> Result<Vec<Node>, ParseError> {
+    let mut statements = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    loop {
+        let t = tokens.next().ok_or(ParseError::UnexpectedEnd)?;
+        let name = |tokens: &mut SplitWhitespace| match tokens.next() {
+            // rebinding a keyword or builtin would change what later statements mean
+            Some(name) if is_reserved(name) => Err(ParseError::ReservedName(name.to_string())),
+            Some(name) if !is_identifier(name) => {
+                Err(ParseError::UnexpectedToken(name.to_string()))
             }
+            Some(name) => Ok(name.to_string()),
+            None => Err(ParseError::MissingName(t.to_string())),
+        };
+        let parsed = match t {
+            "neg" => Parsed::Partial(Partial::Unary(UnaryOp::Neg)),
+
+            "not" => Parsed::Partial(Partial::Unary(UnaryOp::Not)),
 
             "let" => {
-                let name = tokens.next().log_expect("");
-                let value = parse_sentence(tokens, functions).log_expect("");
-                nodes.push(Node::BindExpr(BindExpr {
-                    name: name.to_string(),
-                    value,
-                }));
+                let name = name(tokens)?;
+                let mut rest = tokens.clone();
+                match (rest.next(), rest.next()) {
+                    // `let f sum` binds a reference to `sum`, so `f` is parsed as a call from here on
+                    (Some(target), None) if functions.contains_key(target) => {
+                        tokens.next();
+                        let f = functions[target].clone();
+                        functions.insert(name.clone(), f);
+                        Parsed::Done(vec![Node::BindExpr(BindExpr {
+                            name,
+                            value: vec![Node::FnRef(target.to_string())],
+                        })])
+                    }
+                    (Some(_), _) => Parsed::Partial(Partial::Bind(name)),
+                    // `let x` on its own declares `x` as 0
+                    (None, _) => Parsed::Done(vec![Node::BindExpr(BindExpr {
+                        name,
+                        value: vec![Node::Number(Number(0.0))],
+                    })]),
+                }
             }
 
+            "global" => Parsed::Partial(Partial::Global(name(tokens)?)),
+
+            // the rest of the line is part of the comment, even if it starts with a keyword
             "//" => {
-                return Ok(nodes);
+                tokens.for_each(drop);
+                Parsed::Done(Vec::new())
             }
 
-            "return" => {
-                nodes.push(Node::ReturnExpr(ReturnExpr {
-                    value: parse_sentence(tokens, functions).log_expect(""),
-                }));
-            }
+            "true" | "false" => Parsed::Done(vec![Node::BoolLit(t == "true")]),
 
-            ":=" => {
-                let name = tokens.next().log_expect("");
-                let value = parse_sentence(tokens, functions).log_expect("");
-                nodes.push(Node::MutateExpr(MutateExpr {
-                    name: name.to_string(),
-                    value,
-                }));
-            }
+            "return" => Parsed::Partial(Partial::Return),
 
-            "while" => {
-                let condition = parse_sentence(tokens, functions).log_expect("");
-                let body = Vec::new();
-                nodes.push(Node::WhileExpr(WhileExpr { condition, body }));
-            }
+            ":=" => Parsed::Partial(Partial::Mutate(name(tokens)?)),
 
-            "if" => {
-                let condition = parse_sentence(tokens, functions).log_expect("");
-                let body = Vec::new();
-                let else_body = Vec::new();
-                nodes.push(Node::IfExpr(IfExpr {
-                    condition,
-                    body,
-                    else_body,
-                }));
-            }
+            "while" => Parsed::Partial(Partial::While),
+
+            "for" => Parsed::Partial(Partial::For(name(tokens)?)),
+
+            "if" => Parsed::Partial(Partial::If),
 
-            "fn" => {
-                let name = tokens.next().log_expect("");
-                let args = parse_args(tokens.collect::<Vec<_>>().join(" "), functions);
-                let body = Vec::new();
+            "fn" => Parsed::Done({
+                let name = name(tokens)?;
+                let signature = tokens.collect::<Vec<_>>().join(" ");
+                let (mut signature, mut optimize, mut memo) = (signature, None, false);
+                while let Some(start) = signature.find("#[") {
+                    let end = match signature[start..].find(']') {
+                        Some(end) => start + end,
+                        None => return Err(ParseError::InvalidAttribute(signature)),
+                    };
+                    match signature[start + 2..end].trim() {
+                        "memo" => memo = true,
+                        attribute => optimize = Some(OptimizeHint::new(attribute)?),
+                    }
+                    signature = format!("{}{}", &signature[..start], &signature[end + 1..]);
+                }
+                let signature = signature.trim().to_string();
+                let (params, return_type) = match signature.split_once(':') {
+                    Some((params, ty)) => {
+                        (params.trim_end().to_string(), Some(Type::new(ty.trim())?))
+                    }
+                    None => (signature, None),
+                };
+                let args = parse_args(params, functions)?;
+                check_params(&args)?;
+                let by_reference = args.iter().any(|arg| matches!(arg, Node::Reference(_)));
+                if memo && (by_reference || return_type == Some(Type::Bool)) {
+                    return Err(ParseError::InvalidMemo(name));
+                }
+                let body = Rc::from([]);
                 let expr = FnExpr {
-                    name: name.to_string(),
+                    name: name.clone(),
                     args,
                     body,
+                    return_type,
+                    optimize,
+                    memo,
                 };
-                functions.insert(name.to_string(), expr.clone());
-                nodes.push(Node::FnExpr(expr));
-            }
+                functions.insert(name, expr.clone());
+                vec![Node::FnExpr(expr)]
+            }),
 
-            "print" => {
-                nodes.push(Node::PrintStdoutExpr(PrintStdoutExpr {
-                    value: parse_sentence(tokens, functions).log_expect(""),
-                }));
-            }
+            "print" => Parsed::Partial(Partial::Print),
+
+            "do" => Parsed::Partial(Partial::Block),
 
             _ => {
-                if let Some(_f) = functions.get(t) {
-                    let args = parse_args(tokens.collect::<Vec<_>>().join(" "), functions);
-                    nodes.push(Node::FnCallExpr(FnCallExpr {
-                        name: t.to_string(),
-                        args,
-                    }));
+                if let Ok(op) = Op::new(t) {
+                    Parsed::Partial(Partial::Binary(op))
+                } else if let Some(builtin) = Builtin::new(t) {
+                    Parsed::Partial(Partial::Builtin(builtin))
                 } else {
-                    match Number::new(t) {
-                        Ok(n) => nodes.push(Node::Number(n)),
-                        Err(_) => nodes.push(Node::Variable(t.to_string())),
+                    match t.parse::<Number>() {
+                        Ok(n) => Parsed::Done(vec![Node::Number(n)]),
+                        // an unknown name with arguments is still a call, so calling a variable (e.g.
+                        // `let f 5; f (1)`) fails when evaluated instead of dropping the arguments
+                        Err(_)
+                            if is_identifier(t)
+                                && (functions.contains_key(t)
+                                    || tokens
+                                        .clone()
+                                        .next()
+                                        .is_some_and(|next| next.starts_with('('))) =>
+                        {
+                            parse_call(t, tokens, functions)?
+                        }
+                        Err(_) if is_identifier(t) => {
+                            Parsed::Done(vec![Node::Variable(t.to_string())])
+                        }
+                        // symbols are reserved for operators
+                        Err(_) if t.chars().all(|c| c.is_ascii_punctuation()) => {
+                            return Err(ParseError::InvalidOperator(t.to_string()))
+                        }
+                        Err(_) => return Err(ParseError::UnexpectedToken(t.to_string())),
                     }
                 }
             }
-        },
+        };
 
-        None => {
-            log::warn!("No tokens found in statement; Ignoring");
-            return Err("No tokens found".to_string())
-        },
+        let mut nodes = match parsed {
+            Parsed::Partial(partial) => {
+                let frame = Frame::new(partial);
+                if frame.wants_operand(tokens)? {
+                    if stack.len() == MAX_EXPRESSION_DEPTH {
+                        return Err(ParseError::ExpressionTooDeep);
+                    }
+                    stack.push(frame);
+                    continue;
+                }
+                vec![frame.finish()]
+            }
+            Parsed::Done(nodes) => nodes,
+        };
+
+        // hand the finished expression to its parent, finishing every parent that is now complete
+        loop {
+            let Some(frame) = stack.last_mut() else {
+                // a statement that opens a block takes its body from the next lines instead
+                let opens_block = nodes.last_mut().and_then(pending_block).is_some();
+                statements.append(&mut nodes);
+                match tokens.clone().next() {
+                    Some(t) if STATEMENT_KEYWORDS.contains(&t) && !opens_block => break,
+                    _ => return Ok(statements),
+                }
+            };
+            frame.operands.push(nodes);
+            if frame.wants_operand(tokens)? {
+                break;
+            }
+            nodes = vec![stack.pop().unwrap().finish()];
+        }
     }
+}
 
-    Ok(nodes)
+/// Parse a call to `name`, which takes the parenthesized arguments after it. Like any other operand, the call
+/// can be followed by more operands (e.g. `+ f (1) 2`).
+fn parse_call(
+    name: &str,
+    tokens: &mut SplitWhitespace,
+    functions: &mut HashMap<String, FnExpr>,
+) -> Result<Parsed, ParseError> {
+    // the arguments end with the parenthesis that closes the first one
+    let mut args = Vec::new();
+    let mut depth = 0;
+    for token in tokens {
+        depth += token.matches('(').count() as isize - token.matches(')').count() as isize;
+        args.push(token);
+        if depth <= 0 && args[0].starts_with('(') {
+            break;
+        }
+    }
+    let args = args.join(" ");
+    // defaults belong in the parameters, not the arguments
+    let is_default = |token: &str| split_default(token.trim_matches(['(', ')'])).is_some();
+    if args.split_whitespace().any(is_default) {
+        return Err(ParseError::InvalidArgs(args));
+    }
+    let args = parse_args(args, functions)?;
+    Ok(Parsed::Done(vec![Node::FnCallExpr(FnCallExpr {
+        name: name.to_string(),
+        args,
+    })]))
 }
 
-fn parse_args(tokens: String, functions: &mut HashMap<String, FnExpr>) -> Vec<Node> {
+fn parse_args(
+    tokens: String,
+    functions: &mut HashMap<String, FnExpr>,
+) -> Result<Vec<Node>, ParseError> {
     let mut nodes = Vec::new();
     let mut tokens = tokens;
-    if !tokens.starts_with('(') && !tokens.ends_with(')') {
-        log_and_exit!("Invalid function arguments. Must be in the form (arg1 arg2 ...)");
+    if !tokens.starts_with('(') || !tokens.ends_with(')') {
+        return Err(ParseError::InvalidArgs(tokens));
     }
 
     tokens.remove(0);
     tokens.pop();
 
-    let tokens = tokens.split_whitespace();
-    for token in tokens {
-        if let Ok(mut new_nodes) = parse_sentence(&mut token.split_whitespace(), functions) {
-            nodes.append(&mut new_nodes);
+    let mut tokens = tokens.split_whitespace();
+    while let Some(token) = tokens.clone().next() {
+        // `times=1` is a parameter with a default
+        if let Some((name, default)) = split_default(token) {
+            tokens.next();
+            let value = vec![Node::Number(default.parse()?)];
+            match parse_sentence(&mut name.split_whitespace(), functions)?.pop() {
+                Some(Node::Variable(name)) => nodes.push(Node::BindExpr(BindExpr { name, value })),
+                _ => return Err(ParseError::InvalidArgs(token.to_string())),
+            }
+            continue;
+        }
+
+        // `&x` passes `x` by reference
+        if let Some(name) = token.strip_prefix('&') {
+            tokens.next();
+            match parse_sentence(&mut name.split_whitespace(), functions)?.pop() {
+                Some(Node::Variable(name)) => nodes.push(Node::Reference(name)),
+                _ => return Err(ParseError::InvalidArgs(token.to_string())),
+            }
+            continue;
         }
+
+        // an argument can be a whole expression (e.g. `+ 1 2`), which ends where its last operand does
+        let mut new_nodes = parse_sentence(&mut tokens, functions)?;
+        nodes.append(&mut new_nodes);
     }
 
-    nodes
+    Ok(nodes)
 }
 
-/// Evaluate an AST. This will evaluate an AST and return the result. All variables are in the global scope.
-/// This is essentially the interpreter for the language.
-pub fn eval(
-    ast: &Vec<Node>,
-    globals: &mut HashMap<String, f64>,
-    functions: &mut HashMap<String, FnExpr>,
-) -> f64 {
-    let mut return_val: Option<f64> = None;
-    let mut last_val: f64 = 0.0;
-
-    for node in ast {
-        last_val = match node {
-            Node::Number(n) => n.0,
-            Node::BinaryExpr(e) => {
-                let lhs = eval(&e.lhs, globals, functions);
-                let rhs = eval(&e.rhs, globals, functions);
+/// Split a parameter with a default (e.g. `times=1`) into its name and default. Operators like `==` are not
+/// defaults.
+fn split_default(token: &str) -> Option<(&str, &str)> {
+    match Op::new(token) {
+        Ok(_) => None,
+        Err(_) => token.split_once('='),
+    }
+}
 
-                match e.op {
-                    Op::Add => lhs + rhs,
-                    Op::Sub => lhs - rhs,
-                    Op::Mul => lhs * rhs,
-                    Op::Div => lhs / rhs,
-                    Op::Gt => (lhs > rhs) as i32 as f64,
-                    Op::Lt => (lhs < rhs) as i32 as f64,
-                    Op::Mod => lhs % rhs,
-                    Op::Eqt => (lhs == rhs) as i32 as f64,
-                }
-            }
+/// Check that no two parameters of a function share a name.
+pub(crate) fn check_params(params: &[Node]) -> Result<(), ParseError> {
+    let mut has_default = false;
+    for (i, param) in params.iter().enumerate() {
+        let name = match param {
+            Node::Variable(name) | Node::Reference(name) => name,
             Node::BindExpr(e) => {
-                let value = eval(&e.value, globals, functions);
-                globals.insert(e.name.clone(), value);
-                value
-            }
-            Node::Variable(v) => match globals.get(v) {
-                Some(n) => *n,
-                None => log_and_exit!("Variable not found: {v}"),
-            },
-            Node::ReturnExpr(e) => {
-                return_val = Some(eval(&e.value, globals, functions));
-                0.0 // This doesn't matter, because we'll check return_val at the end
-            }
-            Node::MutateExpr(e) => {
-                let value = eval(&e.value, globals, functions);
-                if let Some(n) = globals.get_mut(&e.name) {
-                    *n = value;
-                } else {
-                    log_and_exit!("Variable not found: {}", e.name);
-                }
-                value
-            }
-            Node::WhileExpr(e) => {
-                while eval(&e.condition, globals, functions) != 0.0 {
-                    eval(&e.body, globals, functions);
-                }
-                0.0
-            }
-            Node::IfExpr(e) => {
-                if eval(&e.condition, globals, functions) != 0.0 {
-                    eval(&e.body, globals, functions)
-                } else {
-                    eval(&e.else_body, globals, functions)
-                }
-            }
-            Node::FnExpr(e) => {
-                functions.insert(e.name.clone(), e.clone());
-                0.0
-            }
-            Node::FnCallExpr(e) => {
-                if let Some(f) = functions.get(&e.name).cloned() {
-                    let mut local_scope = HashMap::new();
-                    for (param, arg) in f.args.iter().zip(&e.args) {
-                        let v = eval(&vec![arg.clone()], globals, functions);
-                        let k = match param {
-                            Node::Variable(v) => v,
-                            _ => log_and_exit!("Invalid function argument"),
-                        };
-                        local_scope.insert(k.clone(), v);
-                    }
-                    eval(&f.body, &mut local_scope, functions)
-                } else {
-                    log_and_exit!("Function not found: {}", e.name);
-                }
-            }
-            Node::PrintStdoutExpr(e) => {
-                let value = eval(&e.value, globals, functions);
-                println!("{}", value);
-                0.0
+                has_default = true;
+                &e.name
             }
+            Node::BoolLit(b) => return Err(ParseError::InvalidArgs(b.to_string())),
+            _ => continue,
         };
+        let duplicate = params[..i].iter().any(|p| param_name(p) == Some(name));
+        if duplicate {
+            return Err(ParseError::DuplicateParam(name.clone()));
+        }
+        // only trailing arguments can be left out, so defaults cannot be followed by required parameters
+        if has_default && !matches!(param, Node::BindExpr(_)) {
+            return Err(ParseError::MissingDefault(name.clone()));
+        }
     }
-
-    return_val.unwrap_or(last_val)
+    Ok(())
 }
 
-pub struct CompileConfig {
-    pub use_jit: bool,
-    pub show_ir: bool,
-    pub optimization_level: u8,
-    pub name: String,
-    pub progress: ProgressBar,
+/// The name of a function parameter: a plain or reference parameter, or one with a default.
+fn param_name(param: &Node) -> Option<&String> {
+    match param {
+        Node::Variable(name) | Node::Reference(name) => Some(name),
+        Node::BindExpr(e) => Some(&e.name),
+        _ => None,
+    }
 }
 
-impl CompileConfig {
-    pub fn from(use_jit: bool, show_ir: bool) -> Self {
-        Self {
-            use_jit,
-            show_ir,
-            optimization_level: 1,
-            name: String::from("main"),
-            progress: ProgressBar::new(0),
-        }
-    }
+/// Convert an AST back into RPN source, one statement per line with block bodies indented by four spaces.
+/// Parsing the result gives back the same AST, so this is used to format source canonically.
+pub fn unparse(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    unparse_body(nodes, 0, &mut out);
+    out
+}
+
+/// Write each node on its own line, followed by the body of a block it leaves open.
+fn unparse_body(nodes: &[Node], depth: usize, out: &mut String) {
+    for node in nodes {
+        let indent = "    ".repeat(depth);
+        out.push_str(&indent);
+        let open = unparse_expr(node, true, out);
+        out.push('\n');
+
+        let Some(open) = open else { continue };
+        match open {
+            Node::WhileExpr(WhileExpr { body, .. })
+            | Node::ForExpr(ForExpr { body, .. })
+            | Node::FnExpr(FnExpr { body, .. })
+            | Node::Block(body) => unparse_body(body, depth + 1, out),
+            Node::IfExpr(e) => {
+                unparse_body(&e.body, depth + 1, out);
+                if !e.else_body.is_empty() {
+                    out.push_str(&format!("{indent}else\n"));
+                    unparse_body(&e.else_body, depth + 1, out);
+                }
+            }
+            _ => (),
+        }
+        out.push_str(&format!("{indent}end\n"));
+    }
+}
+
+/// Write a node on the current line. Only the last operand of a statement can open a block that takes its
+/// body from the next lines (e.g. `let x do`), so a block there is left open and returned; anywhere else a
+/// `do` block is written on the same line.
+fn unparse_expr<'a>(node: &'a Node, last: bool, out: &mut String) -> Option<&'a Node> {
+    let operand = |nodes: &'a [Node], last: bool, out: &mut String| {
+        let mut open = None;
+        for node in nodes {
+            out.push(' ');
+            open = unparse_expr(node, last, out);
+        }
+        open
+    };
+    let args = |nodes: &[Node]| {
+        let mut args = String::new();
+        for node in nodes {
+            match node {
+                Node::BindExpr(param) => {
+                    args.push_str(&format!("{}=", param.name));
+                    for value in &param.value {
+                        unparse_expr(value, false, &mut args);
+                    }
+                }
+                _ => {
+                    unparse_expr(node, false, &mut args);
+                }
+            }
+            args.push(' ');
+        }
+        format!("({})", args.trim_end())
+    };
+
+    match node {
+        Node::Number(n) => out.push_str(&n.0.to_string()),
+        Node::BoolLit(b) => out.push_str(&b.to_string()),
+        Node::Variable(name) | Node::FnRef(name) => out.push_str(name),
+        Node::Reference(name) => out.push_str(&format!("&{name}")),
+        Node::BinaryExpr(e) => {
+            out.push_str(e.op.as_str());
+            operand(&e.lhs, false, out);
+            return operand(&e.rhs, last, out);
+        }
+        Node::UnaryExpr(e) => {
+            out.push_str(&e.op.to_string());
+            return operand(&e.operand, last, out);
+        }
+        Node::BindExpr(e) => {
+            out.push_str(&format!("let {}", e.name));
+            return operand(&e.value, last, out);
+        }
+        Node::GlobalExpr(e) => {
+            out.push_str(&format!("global {}", e.name));
+            return operand(&e.value, last, out);
+        }
+        Node::ReturnExpr(e) => {
+            out.push_str("return");
+            return operand(&e.value, last, out);
+        }
+        Node::MutateExpr(e) => {
+            out.push_str(&format!(":= {}", e.name));
+            return operand(&e.value, last, out);
+        }
+        Node::PrintStdoutExpr(e) => {
+            out.push_str("print");
+            return operand(&e.value, last, out);
+        }
+        Node::BuiltinExpr(e) => {
+            out.push_str(e.builtin.name());
+            let mut open = None;
+            for (i, arg) in e.args.iter().enumerate() {
+                open = operand(arg, last && i + 1 == e.args.len(), out);
+            }
+            return open;
+        }
+        Node::FnCallExpr(e) => out.push_str(&format!("{} {}", e.name, args(&e.args))),
+        Node::WhileExpr(e) => {
+            out.push_str("while");
+            operand(&e.condition, false, out);
+            return last.then_some(node);
+        }
+        Node::ForExpr(e) => {
+            out.push_str(&format!("for {}", e.var));
+            operand(&e.start, false, out);
+            operand(&e.end, false, out);
+            // a step of 1 is the default
+            if e.step != [Node::Number(Number(1.0))] {
+                operand(&e.step, false, out);
+            }
+            return last.then_some(node);
+        }
+        Node::IfExpr(e) => {
+            out.push_str("if");
+            operand(&e.condition, false, out);
+            return last.then_some(node);
+        }
+        Node::FnExpr(e) => {
+            out.push_str(&format!("fn {} {}", e.name, args(&e.args)));
+            if let Some(ty) = e.return_type {
+                out.push_str(&format!(" : {ty}"));
+            }
+            if let Some(hint) = e.optimize {
+                out.push_str(&format!(" #[{hint}]"));
+            }
+            if e.memo {
+                out.push_str(" #[memo]");
+            }
+            return last.then_some(node);
+        }
+        Node::Block(body) => {
+            out.push_str("do");
+            if last {
+                return Some(node);
+            }
+            operand(body, false, out);
+            out.push_str(" end");
+        }
+    }
+    None
+}
+
+/// Convert an AST into a Graphviz DOT graph. Each node is labeled with its variant (and its operator, name or
+/// value), with an edge to each of its children labeled by the child's role (e.g. `lhs`, `condition`).
+pub fn ast_to_dot(nodes: &[Node]) -> String {
+    let mut out = String::from("digraph ast {\n    node [shape=box];\n");
+    let mut next = 0;
+    for node in nodes {
+        dot_node(node, &mut next, &mut out);
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Write a node and its children to a DOT graph, numbering them from `next`. Returns the node's id.
+fn dot_node(node: &Node, next: &mut usize, out: &mut String) -> usize {
+    let id = *next;
+    *next += 1;
+    let (label, children): (String, Vec<(&str, &[Node])>) = match node {
+        Node::Number(n) => (format!("Number {}", n.0), vec![]),
+        Node::BoolLit(b) => (format!("BoolLit {b}"), vec![]),
+        Node::BinaryExpr(e) => (
+            format!("BinaryExpr {}", e.op),
+            vec![("lhs", &e.lhs), ("rhs", &e.rhs)],
+        ),
+        Node::UnaryExpr(e) => (format!("UnaryExpr {}", e.op), vec![("operand", &e.operand)]),
+        Node::BindExpr(e) => (format!("BindExpr {}", e.name), vec![("value", &e.value)]),
+        Node::GlobalExpr(e) => (format!("GlobalExpr {}", e.name), vec![("value", &e.value)]),
+        Node::Variable(name) => (format!("Variable {name}"), vec![]),
+        Node::Reference(name) => (format!("Reference {name}"), vec![]),
+        Node::FnRef(name) => (format!("FnRef {name}"), vec![]),
+        Node::ReturnExpr(e) => ("ReturnExpr".to_string(), vec![("value", &e.value)]),
+        Node::MutateExpr(e) => (format!("MutateExpr {}", e.name), vec![("value", &e.value)]),
+        Node::PrintStdoutExpr(e) => ("PrintStdoutExpr".to_string(), vec![("value", &e.value)]),
+        Node::WhileExpr(e) => (
+            "WhileExpr".to_string(),
+            vec![("condition", &e.condition), ("body", &e.body)],
+        ),
+        Node::ForExpr(e) => (
+            format!("ForExpr {}", e.var),
+            vec![
+                ("start", &e.start),
+                ("end", &e.end),
+                ("step", &e.step),
+                ("body", &e.body),
+            ],
+        ),
+        Node::IfExpr(e) => (
+            "IfExpr".to_string(),
+            vec![
+                ("condition", &e.condition),
+                ("body", &e.body),
+                ("else", &e.else_body),
+            ],
+        ),
+        Node::FnExpr(e) => (
+            format!("FnExpr {}", e.name),
+            vec![("param", &e.args), ("body", &e.body)],
+        ),
+        Node::FnCallExpr(call) => (
+            format!("FnCallExpr {}", call.name),
+            vec![("arg", &call.args)],
+        ),
+        Node::BuiltinExpr(e) => (
+            format!("BuiltinExpr {}", e.builtin),
+            e.args.iter().map(|arg| ("arg", arg.as_slice())).collect(),
+        ),
+        Node::Block(body) => ("Block".to_string(), vec![("body", body)]),
+    };
+    // Debug quotes and escapes the label the way DOT expects
+    out.push_str(&format!("    n{id} [label={label:?}];\n"));
+    for (role, body) in children {
+        for child in body {
+            let child = dot_node(child, next, out);
+            out.push_str(&format!("    n{id} -> n{child} [label={role:?}];\n"));
+        }
+    }
+    id
+}
+
+/// Convert a comparison result to a number: `1` if true, `0` if false.
+pub fn bool_to_num(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Convert a number to a condition: any number other than `0` is true.
+pub fn num_to_bool(n: f64) -> bool {
+    n != 0.0
+}
+
+/// Check that no comparison result is used as a number (e.g. `+ > 2 1 5`). Comparisons, `bool` functions and
+/// blocks ending in one may still be used as conditions. Only needed when
+/// [`CompileConfig::implicit_bool_coercion`] is off.
+pub fn check_bool_coercion(nodes: &[Node]) -> Result<(), ParseError> {
+    let mut bool_functions = Vec::new();
+    check_bool_operands(nodes, &mut bool_functions)
+}
+
+/// Whether an operand is statically a comparison result.
+fn is_bool(operand: &[Node], bool_functions: &[String]) -> bool {
+    match operand.last() {
+        Some(Node::BinaryExpr(e)) => e.op.is_comparison() || e.op.is_logical(),
+        Some(Node::UnaryExpr(e)) => e.op == UnaryOp::Not,
+        Some(Node::BoolLit(_)) => true,
+        Some(Node::FnCallExpr(e)) => bool_functions.contains(&e.name),
+        Some(Node::Block(body)) => is_bool(body, bool_functions),
+        _ => false,
+    }
+}
+
+fn check_bool_operands(nodes: &[Node], bool_functions: &mut Vec<String>) -> Result<(), ParseError> {
+    for node in nodes {
+        match node {
+            Node::BinaryExpr(e) => {
+                // bools can still be compared with each other and combined
+                if e.op != Op::Eqt
+                    && !e.op.is_logical()
+                    && (is_bool(&e.lhs, bool_functions) || is_bool(&e.rhs, bool_functions))
+                {
+                    return Err(ParseError::BoolAsNumber(e.op.to_string()));
+                }
+                check_bool_operands(&e.lhs, bool_functions)?;
+                check_bool_operands(&e.rhs, bool_functions)?;
+            }
+            Node::UnaryExpr(e) => {
+                if e.op == UnaryOp::Neg && is_bool(&e.operand, bool_functions) {
+                    return Err(ParseError::BoolAsNumber(e.op.to_string()));
+                }
+                check_bool_operands(&e.operand, bool_functions)?;
+            }
+            Node::FnExpr(e) => {
+                if e.return_type == Some(Type::Bool) {
+                    bool_functions.push(e.name.clone());
+                }
+                check_bool_operands(&e.body, bool_functions)?;
+            }
+            Node::BindExpr(e) | Node::GlobalExpr(e) => {
+                if let [Node::FnRef(target)] = e.value.as_slice() {
+                    if bool_functions.contains(target) {
+                        bool_functions.push(e.name.clone());
+                    }
+                }
+                check_bool_operands(&e.value, bool_functions)?
+            }
+            Node::ReturnExpr(e) => check_bool_operands(&e.value, bool_functions)?,
+            Node::MutateExpr(e) => check_bool_operands(&e.value, bool_functions)?,
+            Node::PrintStdoutExpr(e) => check_bool_operands(&e.value, bool_functions)?,
+            Node::WhileExpr(e) => {
+                check_bool_operands(&e.condition, bool_functions)?;
+                check_bool_operands(&e.body, bool_functions)?;
+            }
+            Node::ForExpr(e) => {
+                for operand in [&e.start, &e.end, &e.step] {
+                    check_bool_operands(operand, bool_functions)?;
+                }
+                check_bool_operands(&e.body, bool_functions)?;
+            }
+            Node::IfExpr(e) => {
+                check_bool_operands(&e.condition, bool_functions)?;
+                check_bool_operands(&e.body, bool_functions)?;
+                check_bool_operands(&e.else_body, bool_functions)?;
+            }
+            Node::FnCallExpr(e) => check_bool_operands(&e.args, bool_functions)?,
+            Node::BuiltinExpr(e) => {
+                for arg in &e.args {
+                    if is_bool(arg, bool_functions) {
+                        return Err(ParseError::BoolAsNumber(e.builtin.to_string()));
+                    }
+                    check_bool_operands(arg, bool_functions)?;
+                }
+            }
+            Node::Block(body) => check_bool_operands(body, bool_functions)?,
+            Node::Number(_)
+            | Node::BoolLit(_)
+            | Node::Variable(_)
+            | Node::Reference(_)
+            | Node::FnRef(_) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Apply the parts of `config` that change or restrict a parsed program: start it in the entry function if
+/// one is set, then run the checks for the implicit conversions that are off and for deterministic mode.
+pub fn check_config(nodes: Vec<Node>, config: &CompileConfig) -> Result<Vec<Node>, ParseError> {
+    let nodes = match &config.entry {
+        Some(entry) => with_entry(nodes, entry)?,
+        None => nodes,
+    };
+    if !config.implicit_bool_coercion {
+        check_bool_coercion(&nodes)?;
+    }
+    if !config.implicit_else {
+        check_if_values(&nodes)?;
+    }
+    if config.deterministic {
+        check_deterministic(&nodes, config.seed.is_some())?;
+    }
+    Ok(nodes)
+}
+
+/// Check that every `if` used as an operand has an `else` (e.g. `let x if > a b` with no `else` is rejected), so it
+/// has a value whichever branch runs. `if` statements in bodies are not operands. Only needed when
+/// [`CompileConfig::implicit_else`] is off.
+pub fn check_if_values(nodes: &[Node]) -> Result<(), ParseError> {
+    for node in nodes {
+        let children = children(node);
+        // the bodies of loops, ifs, functions and blocks hold statements, and everything before them is an operand
+        let operands = match node {
+            Node::WhileExpr(_) | Node::IfExpr(_) => 1,
+            Node::ForExpr(_) => 3,
+            Node::FnExpr(_) | Node::Block(_) => 0,
+            _ => children.len(),
+        };
+        for (i, body) in children.into_iter().enumerate() {
+            if i < operands {
+                check_if_value(body)?;
+            }
+            check_if_values(body)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that a program gives the same result on every run: it cannot read the `clock`, and can only use
+/// `rand` and `randint` if the generator is `seeded`. Only needed when [`CompileConfig::deterministic`] is on.
+pub fn check_deterministic(nodes: &[Node], seeded: bool) -> Result<(), ParseError> {
+    for node in nodes {
+        if let Node::BuiltinExpr(e) = node {
+            let random = matches!(e.builtin, Builtin::Rand | Builtin::RandInt);
+            if e.builtin == Builtin::Clock || (random && !seeded) {
+                return Err(ParseError::NonDeterministic(e.builtin));
+            }
+        }
+        for body in children(node) {
+            check_deterministic(body, seeded)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every body directly inside a node: its operands, then the bodies of a block or function.
+fn children(node: &Node) -> Vec<&[Node]> {
+    match node {
+        Node::BinaryExpr(e) => vec![&e.lhs, &e.rhs],
+        Node::UnaryExpr(e) => vec![&e.operand],
+        Node::BindExpr(e) | Node::GlobalExpr(e) => vec![&e.value],
+        Node::ReturnExpr(e) => vec![&e.value],
+        Node::MutateExpr(e) => vec![&e.value],
+        Node::PrintStdoutExpr(e) => vec![&e.value],
+        Node::WhileExpr(e) => vec![&e.condition, &e.body],
+        Node::ForExpr(e) => vec![&e.start, &e.end, &e.step, &e.body],
+        Node::IfExpr(e) => vec![&e.condition, &e.body, &e.else_body],
+        Node::FnExpr(e) => vec![&e.body],
+        Node::FnCallExpr(e) => e.args.iter().map(std::slice::from_ref).collect(),
+        Node::BuiltinExpr(e) => e.args.iter().map(Vec::as_slice).collect(),
+        Node::Block(body) => vec![body],
+        Node::Number(_)
+        | Node::BoolLit(_)
+        | Node::Variable(_)
+        | Node::Reference(_)
+        | Node::FnRef(_) => Vec::new(),
+    }
+}
+
+/// Like [`children`], but mutable. The operands come apart from the bodies of loops, ifs, functions and blocks,
+/// which are shared and have to be copied to be changed.
+fn children_mut(node: &mut Node) -> (Vec<&mut Vec<Node>>, Vec<&mut Rc<[Node]>>) {
+    match node {
+        Node::BinaryExpr(e) => (vec![&mut e.lhs, &mut e.rhs], Vec::new()),
+        Node::UnaryExpr(e) => (vec![&mut e.operand], Vec::new()),
+        Node::BindExpr(e) | Node::GlobalExpr(e) => (vec![&mut e.value], Vec::new()),
+        Node::ReturnExpr(e) => (vec![&mut e.value], Vec::new()),
+        Node::MutateExpr(e) => (vec![&mut e.value], Vec::new()),
+        Node::PrintStdoutExpr(e) => (vec![&mut e.value], Vec::new()),
+        Node::WhileExpr(e) => (vec![&mut e.condition], vec![&mut e.body]),
+        Node::ForExpr(e) => (
+            vec![&mut e.start, &mut e.end, &mut e.step],
+            vec![&mut e.body],
+        ),
+        Node::IfExpr(e) => (vec![&mut e.condition], vec![&mut e.body, &mut e.else_body]),
+        Node::FnExpr(e) => (Vec::new(), vec![&mut e.body]),
+        Node::FnCallExpr(e) => (vec![&mut e.args], Vec::new()),
+        Node::BuiltinExpr(e) => (e.args.iter_mut().collect(), Vec::new()),
+        Node::Block(body) => (Vec::new(), vec![body]),
+        Node::Number(_)
+        | Node::BoolLit(_)
+        | Node::Variable(_)
+        | Node::Reference(_)
+        | Node::FnRef(_) => (Vec::new(), Vec::new()),
+    }
+}
+
+/// Check that the value of an operand does not come from an `if` without an `else`.
+fn check_if_value(operand: &[Node]) -> Result<(), ParseError> {
+    match operand.last() {
+        Some(Node::IfExpr(e)) if e.else_body.is_empty() => Err(ParseError::IfWithoutElse),
+        Some(Node::IfExpr(e)) => {
+            check_if_value(&e.body)?;
+            check_if_value(&e.else_body)
+        }
+        Some(Node::Block(body)) => check_if_value(body),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a body has no value, because none of its statements can produce one (e.g. `print 5`).
+fn is_unit(body: &[Node]) -> bool {
+    match body.iter().rev().find(|node| node.has_value()) {
+        Some(Node::Block(body)) => is_unit(body),
+        Some(_) => false,
+        None => true,
+    }
+}
+
+/// Check that no operand of an operator or builtin is a body without a value (e.g. `+ print 1 2`).
+fn check_unit(name: &dyn fmt::Display, operands: &[&[Node]]) -> Result<(), RuntimeError> {
+    // a loop that is the operand itself evaluates to its last iteration
+    let unit = |operand: &[Node]| {
+        !matches!(operand, [Node::WhileExpr(_) | Node::ForExpr(_)]) && is_unit(operand)
+    };
+    if operands.iter().any(|operand| unit(operand)) {
+        return Err(RuntimeError::UnitOperand(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Evaluate an AST like [`eval`], but return [`Value::Unit`] for a program without a value (e.g. `print 5`)
+/// instead of `0`.
+pub fn eval_value(
+    ast: &[Node],
+    globals: &mut HashMap<String, f64>,
+    functions: &mut HashMap<String, FnExpr>,
+) -> Result<Value, RuntimeError> {
+    let value = eval_program(
+        ast,
+        globals,
+        functions,
+        Printer::new(&mut std::io::stdout()),
+    )?;
+    Ok(match value {
+        None => Value::Unit,
+        Some(value) if is_bool_value(ast) => Value::Bool(num_to_bool(value)),
+        Some(value) => Value::Number(value),
+    })
+}
+
+/// Whether a body evaluates to a bool, going by its last statement with a value (e.g. `let b false`).
+fn is_bool_value(body: &[Node]) -> bool {
+    match body.iter().rev().find(|node| node.has_value()) {
+        Some(Node::BindExpr(e) | Node::GlobalExpr(e)) => is_bool_value(&e.value),
+        Some(
+            Node::ReturnExpr(ReturnExpr { value }) | Node::MutateExpr(MutateExpr { value, .. }),
+        ) => is_bool_value(value),
+        Some(node) => is_bool(std::slice::from_ref(node), &[]),
+        None => false,
+    }
+}
+
+/// Evaluate an AST. This will evaluate an AST and return the result. Top-level variables are in `globals`,
+/// and each function call gets its own scope on top of it. Calls do not recurse on the native stack, so a
+/// deeply recursive program is only limited by memory.
+/// This is essentially the interpreter for the language. Returns a [`RuntimeError`] if the AST cannot be evaluated.
+pub fn eval(
+    ast: &[Node],
+    globals: &mut HashMap<String, f64>,
+    functions: &mut HashMap<String, FnExpr>,
+) -> Result<f64, RuntimeError> {
+    eval_with_output(
+        ast,
+        globals,
+        functions,
+        Printer::new(&mut std::io::stdout()),
+    )
+}
+
+/// Evaluate an AST like [`eval`], printing values with `out` instead of to stdout.
+pub fn eval_with_output(
+    ast: &[Node],
+    globals: &mut HashMap<String, f64>,
+    functions: &mut HashMap<String, FnExpr>,
+    out: Printer,
+) -> Result<f64, RuntimeError> {
+    Ok(eval_program(ast, globals, functions, out)?.unwrap_or(0.0))
+}
+
+/// Evaluate a program, returning `None` if it has no value because no statement with one ran and no `return`
+/// fired.
+fn eval_program(
+    ast: &[Node],
+    globals: &mut HashMap<String, f64>,
+    functions: &mut HashMap<String, FnExpr>,
+    mut out: Printer,
+) -> Result<Option<f64>, RuntimeError> {
+    let mut scopes = vec![std::mem::take(globals)];
+    let result = eval_scoped(ast, &mut scopes, functions, &mut out, None);
+    *globals = scopes.swap_remove(0);
+    result
+}
+
+/// Where and how `print` writes values.
+pub struct Printer<'a> {
+    /// Where values are written. It is not flushed.
+    pub writer: &'a mut dyn Write,
+    /// How numbers are written
+    pub float_format: FloatFormat,
+    /// Also print the value of every top-level statement that has one, like a REPL transcript
+    pub print_all: bool,
+}
+
+impl<'a> Printer<'a> {
+    /// Print to `writer` in the default format, and only what `print` prints.
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        Self {
+            writer,
+            float_format: FloatFormat::Default,
+            print_all: false,
+        }
+    }
+
+    fn print(&mut self, value: f64) -> Result<(), RuntimeError> {
+        // stdout is line buffered, so unless `out` buffers more, output is kept if the program fails later
+        writeln!(self.writer, "{}", self.float_format.format(value))
+            .map_err(|e| RuntimeError::Output(e.to_string()))
+    }
+}
+
+/// Find a variable in the current scope, falling back to the global scope.
+fn lookup<'a>(scopes: &'a mut [HashMap<String, f64>], name: &str) -> Option<&'a mut f64> {
+    let depth = if scopes.last()?.contains_key(name) {
+        scopes.len() - 1
+    } else {
+        0
+    };
+    scopes[depth].get_mut(name)
+}
+
+fn binary(op: &Op, lhs: f64, rhs: f64) -> f64 {
+    match op {
+        Op::Add => lhs + rhs,
+        Op::Sub => lhs - rhs,
+        Op::Mul => lhs * rhs,
+        Op::Div => lhs / rhs,
+        Op::Gt => bool_to_num(lhs > rhs),
+        Op::Lt => bool_to_num(lhs < rhs),
+        Op::Mod => lhs % rhs,
+        Op::Eqt => bool_to_num(lhs == rhs),
+        Op::EuclidMod => lhs.rem_euclid(rhs),
+        Op::And => bool_to_num(lhs != 0.0 && rhs != 0.0),
+        Op::Or => bool_to_num(lhs != 0.0 || rhs != 0.0),
+    }
+}
+
+fn unary(op: UnaryOp, value: f64) -> f64 {
+    match op {
+        UnaryOp::Neg => -value,
+        UnaryOp::Not => bool_to_num(!num_to_bool(value)),
+    }
+}
+
+/// Evaluate an AST on a stack of scopes. The first scope is the global scope and the last is the current one.
+/// The evaluation runs on an explicit stack of [`Task`]s instead of recursing, so the recursion depth of a
+/// läspa program is limited by heap memory rather than the native stack. If `fuel` is set, the evaluation fails
+/// with [`RuntimeError::StepLimit`] after that many steps.
+fn eval_scoped(
+    ast: &[Node],
+    scopes: &mut Vec<HashMap<String, f64>>,
+    functions: &mut HashMap<String, FnExpr>,
+    out: &mut Printer,
+    fuel: Option<usize>,
+) -> Result<Option<f64>, RuntimeError> {
+    // the tasks borrow the function bodies, so functions defined while evaluating are kept aside until the end
+    let previous = std::mem::take(functions);
+    let mut evaluator = Evaluator {
+        tasks: Vec::new(),
+        values: Vec::new(),
+        scopes,
+        functions: &previous,
+        defined: HashMap::new(),
+        memo: HashMap::new(),
+        out,
+        fuel,
+        valued: false,
+    };
+    evaluator.push_body(ast);
+    let result = evaluator
+        .run()
+        .map(|value| evaluator.valued.then_some(value));
+
+    let defined: Vec<(String, FnExpr)> = evaluator
+        .defined
+        .into_iter()
+        .map(|(name, f)| (name, f.clone()))
+        .collect();
+    *functions = previous;
+    functions.extend(defined);
+    result
+}
+
+/// A step of the evaluation in [`eval_scoped`]. Each step takes the values it needs from the value stack and
+/// pushes its result, or pushes the steps that compute it.
+enum Task<'n> {
+    /// Evaluate the nodes of a body in order, then push the value of its `return` or of its last statement
+    /// that produces a value. When it resumes, the value of the node before `next` is on the value stack.
+    Body {
+        nodes: &'n [Node],
+        next: usize,
+        return_val: Option<f64>,
+        last_val: f64,
+        /// Whether the body is a whole operand, so a loop in it evaluates to its last iteration
+        operand: bool,
+        /// Whether a `return` fired in the statement before `next`, so its value counts even if it is a loop
+        returned: bool,
+        /// Whether the body has a value so far, because a statement with one ran or a `return` fired in it
+        valued: bool,
+    },
+    /// Compute the value of a node from the values of its operands.
+    Finish(&'n Node),
+    /// Use the value on the stack as the return value of the body below.
+    Return,
+    /// Run the body of a while loop if its condition is true. Otherwise the loop evaluates to `last`, the value
+    /// of the body in the last iteration.
+    While { e: &'n WhileExpr, last: f64 },
+    /// Keep the value of the body of a while loop and check its condition again.
+    WhileBody(&'n WhileExpr),
+    /// Run the body of a for loop while its variable is within the bound. After the body, its value is kept as
+    /// `last` and the variable is stepped first.
+    For {
+        e: &'n ForExpr,
+        end: f64,
+        step: f64,
+        frame: usize,
+        stepped: bool,
+        last: f64,
+    },
+    /// Run the branch of an if expression chosen by its condition.
+    If(&'n IfExpr),
+    /// Bind the argument on the stack to its parameter, then evaluate the next argument or the function body.
+    Arg(Call<'n>),
+    /// Leave a function, dropping its scope and copying its reference parameters back to the caller.
+    Leave(Call<'n>),
+}
+
+/// A function call in progress.
+struct Call<'n> {
+    call: &'n FnCallExpr,
+    f: &'n FnExpr,
+    /// The number of arguments evaluated so far
+    args: usize,
+    scope: HashMap<String, f64>,
+    references: Vec<(&'n String, &'n String)>,
+    /// The argument values of a `#[memo]` function, to cache its result under
+    memo_key: Option<Vec<u64>>,
+}
+
+/// The state of [`eval_scoped`].
+struct Evaluator<'n, 'a, 'w> {
+    tasks: Vec<Task<'n>>,
+    values: Vec<f64>,
+    scopes: &'a mut Vec<HashMap<String, f64>>,
+    /// The functions defined before the evaluation started
+    functions: &'n HashMap<String, FnExpr>,
+    /// The functions defined (or bound with `let`) since, which take precedence
+    defined: HashMap<String, &'n FnExpr>,
+    /// The cached results of each `#[memo]` function, keyed by the bits of its argument values
+    memo: HashMap<*const FnExpr, HashMap<Vec<u64>, f64>>,
+    out: &'a mut Printer<'w>,
+    /// The number of steps left, if the evaluation is limited
+    fuel: Option<usize>,
+    /// Whether the last body to finish had a value
+    valued: bool,
+}
+
+impl<'n> Evaluator<'n, '_, '_> {
+    fn run(&mut self) -> Result<f64, RuntimeError> {
+        let result = self.run_tasks();
+        if result.is_err() {
+            // leave the functions that were running, like returning from each of them would
+            while let Some(task) = self.tasks.pop() {
+                if let Task::Leave(call) = task {
+                    self.leave(&call.references);
+                }
+            }
+        }
+        result
+    }
+
+    fn run_tasks(&mut self) -> Result<f64, RuntimeError> {
+        while let Some(task) = self.tasks.pop() {
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    return Err(RuntimeError::StepLimit);
+                }
+                *fuel -= 1;
+            }
+            self.step(task)?;
+        }
+        Ok(self.pop())
+    }
+
+    fn step(&mut self, task: Task<'n>) -> Result<(), RuntimeError> {
+        match task {
+            Task::Body {
+                nodes,
+                mut next,
+                return_val,
+                mut last_val,
+                operand,
+                returned,
+                mut valued,
+            } => {
+                // the body of the program is the only one at the bottom of the stack
+                let print_all = self.out.print_all && self.tasks.is_empty();
+                if next > 0 {
+                    let value = self.pop();
+                    let node = &nodes[next - 1];
+                    if node.has_value() || operand || returned {
+                        last_val = value;
+                        // a block has a value only if its body does
+                        valued |= !matches!(node, Node::Block(_)) || self.valued;
+                        if print_all {
+                            self.out.print(value)?;
+                        }
+                    }
+                }
+                while let Some(node) = nodes.get(next) {
+                    next += 1;
+                    match self.simple(node) {
+                        Some(value) => {
+                            last_val = value;
+                            valued = true;
+                            if print_all {
+                                self.out.print(value)?;
+                            }
+                        }
+                        None => {
+                            self.tasks.push(Task::Body {
+                                nodes,
+                                next,
+                                return_val,
+                                last_val,
+                                operand,
+                                returned: false,
+                                valued,
+                            });
+                            return self.start(node);
+                        }
+                    }
+                }
+                self.valued = valued || return_val.is_some();
+                self.values.push(return_val.unwrap_or(last_val));
+            }
+            Task::Finish(node) => self.finish(node)?,
+            Task::Return => {
+                let value = self.pop();
+                if let Some(Task::Body { return_val, .. }) = self.tasks.last_mut() {
+                    *return_val = Some(value);
+                }
+                // the loops (and other statements) the `return` is in have a value, up to its function
+                let outer = self.tasks.iter_mut().rev().skip(1);
+                for task in outer.take_while(|task| !matches!(task, Task::Leave(_))) {
+                    if let Task::Body { returned, .. } = task {
+                        *returned = true;
+                    }
+                }
+                self.values.push(0.0); // This doesn't matter, because the body uses its return value
+            }
+            Task::While { e, last } => {
+                let condition = self.pop();
+                self.run_while(e, condition, last);
+            }
+            Task::WhileBody(e) => {
+                let last = self.pop();
+                self.check_while(e, last);
+            }
+            Task::For {
+                e,
+                end,
+                step,
+                frame,
+                stepped,
+                mut last,
+            } => {
+                if stepped {
+                    last = self.pop();
+                    if let Some(i) = self.scopes[frame].get_mut(&e.var) {
+                        *i += step;
+                    }
+                }
+                let i = self.scopes[frame][&e.var];
+                if (step > 0.0 && i < end) || (step < 0.0 && i > end) {
+                    self.tasks.push(Task::For {
+                        e,
+                        end,
+                        step,
+                        frame,
+                        stepped: true,
+                        last,
+                    });
+                    self.push_body(&e.body);
+                } else {
+                    self.values.push(last);
+                }
+            }
+            Task::If(e) => {
+                let condition = self.pop();
+                self.run_if(e, condition);
+            }
+            Task::Arg(call) => self.arg(call)?,
+            Task::Leave(call) => {
+                let value = self.pop();
+                self.leave(&call.references);
+                if call.f.return_type == Some(Type::Bool) && value != 0.0 && value != 1.0 {
+                    return Err(RuntimeError::ReturnTypeMismatch {
+                        function: call.call.name.clone(),
+                        expected: Type::Bool,
+                    });
+                }
+                if let Some(key) = call.memo_key {
+                    self.memo.entry(call.f).or_default().insert(key, value);
+                }
+                self.values.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start evaluating a node, pushing its value or the tasks that compute it.
+    fn start(&mut self, node: &'n Node) -> Result<(), RuntimeError> {
+        match node {
+            Node::Number(n) => self.values.push(n.0),
+            Node::BoolLit(b) => self.values.push(bool_to_num(*b)),
+            Node::Variable(v) | Node::Reference(v) => {
+                let value = self.variable(v)?;
+                self.values.push(value);
+            }
+            Node::FnRef(name) => return Err(RuntimeError::FunctionAsValue(name.clone())),
+            Node::BinaryExpr(e) => {
+                check_unit(&e.op, &[&e.lhs, &e.rhs])?;
+                self.finish_after(node, &[&e.lhs, &e.rhs])?;
+            }
+            Node::UnaryExpr(e) => {
+                check_unit(&e.op, &[&e.operand])?;
+                self.finish_after(node, &[&e.operand])?;
+            }
+            Node::BindExpr(e) => match e.value.as_slice() {
+                // binding a function reference adds the function under the new name
+                [Node::FnRef(target)] => {
+                    let f = self.function(target)?;
+                    self.defined.insert(e.name.clone(), f);
+                    self.values.push(0.0);
+                }
+                _ => self.finish_after(node, &[&e.value])?,
+            },
+            Node::GlobalExpr(e) => self.finish_after(node, &[&e.value])?,
+            Node::MutateExpr(e) => self.finish_after(node, &[&e.value])?,
+            Node::PrintStdoutExpr(e) => self.finish_after(node, &[&e.value])?,
+            Node::ForExpr(e) => self.finish_after(node, &[&e.start, &e.end, &e.step])?,
+            Node::BuiltinExpr(e) => {
+                let args: Vec<&[Node]> = e.args.iter().map(Vec::as_slice).collect();
+                check_unit(&e.builtin, &args)?;
+                self.finish_after(node, &args)?;
+            }
+            Node::ReturnExpr(e) => {
+                self.tasks.push(Task::Return);
+                self.push_operand(&e.value);
+            }
+            Node::WhileExpr(e) => self.check_while(e, 0.0),
+            Node::IfExpr(e) => match self.operand(&e.condition) {
+                Some(condition) => self.run_if(e, condition),
+                None => {
+                    self.tasks.push(Task::If(e));
+                    self.push_operand(&e.condition);
+                }
+            },
+            Node::FnExpr(e) => {
+                self.defined.insert(e.name.clone(), e);
+                self.values.push(0.0);
+            }
+            Node::FnCallExpr(call) => {
+                let f = self.function(&call.name)?;
+                self.tasks.push(Task::Arg(Call {
+                    call,
+                    f,
+                    args: 0,
+                    scope: HashMap::new(),
+                    references: Vec::new(),
+                    memo_key: None,
+                }));
+            }
+            Node::Block(body) => self.push_body(body),
+        }
+        Ok(())
+    }
+
+    /// Compute the value of a node from the values of its operands on the stack.
+    fn finish(&mut self, node: &'n Node) -> Result<(), RuntimeError> {
+        let value = match node {
+            Node::BinaryExpr(e) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                binary(&e.op, lhs, rhs)
+            }
+            Node::UnaryExpr(e) => {
+                let value = self.pop();
+                unary(e.op, value)
+            }
+            Node::BindExpr(e) => {
+                let value = self.pop();
+                self.scopes
+                    .last_mut()
+                    .expect("No variable scopes found")
+                    .insert(e.name.clone(), value);
+                value
+            }
+            Node::GlobalExpr(e) => {
+                let value = self.pop();
+                self.scopes[0].insert(e.name.clone(), value);
+                value
+            }
+            Node::MutateExpr(e) => {
+                let value = self.pop();
+                match lookup(self.scopes, &e.name) {
+                    Some(n) => *n = value,
+                    None => return Err(RuntimeError::UndefinedVariable(e.name.clone())),
+                }
+                value
+            }
+            Node::PrintStdoutExpr(_) => {
+                let value = self.pop();
+                self.out.print(value)?;
+                0.0
+            }
+            Node::BuiltinExpr(e) => {
+                let args = self.values.split_off(self.values.len() - e.args.len());
+                e.builtin.eval(&args)?
+            }
+            // a for loop starts once its start, bound and step are evaluated
+            Node::ForExpr(e) => {
+                let step = self.pop();
+                let end = self.pop();
+                let start = self.pop();
+                let frame = self.scopes.len() - 1;
+                self.scopes[frame].insert(e.var.clone(), start);
+                self.tasks.push(Task::For {
+                    e,
+                    end,
+                    step,
+                    frame,
+                    stepped: false,
+                    last: 0.0,
+                });
+                return Ok(());
+            }
+            _ => unreachable!("{node:?} has no operands"),
+        };
+        self.values.push(value);
+        Ok(())
+    }
+
+    /// Bind the argument just evaluated, then evaluate the next one or enter the function.
+    fn arg(&mut self, mut call: Call<'n>) -> Result<(), RuntimeError> {
+        let (e, f) = (call.call, call.f);
+        if call.args > 0 {
+            let value = self.pop();
+            let name = match (&f.args[call.args - 1], e.args.get(call.args - 1)) {
+                (Node::Variable(k), _) => k,
+                (Node::BindExpr(param), _) => &param.name,
+                (Node::Reference(k), Some(Node::Variable(v) | Node::Reference(v))) => {
+                    call.references.push((k, v));
+                    k
+                }
+                (Node::Reference(k), _) => return Err(RuntimeError::InvalidReference(k.clone())),
+                _ => return Err(RuntimeError::InvalidParameter(e.name.clone())),
+            };
+            call.scope.insert(name.clone(), value);
+        }
+
+        // extra arguments are ignored, and missing ones take the parameter's default if it has one
+        let arg = match (f.args.get(call.args), e.args.get(call.args)) {
+            (Some(_), Some(arg)) => Some(std::slice::from_ref(arg)),
+            (Some(Node::BindExpr(param)), None) => Some(param.value.as_slice()),
+            _ => None,
+        };
+        if let Some(arg) = arg {
+            call.args += 1;
+            match self.operand(arg) {
+                Some(value) => {
+                    self.values.push(value);
+                    return self.arg(call);
+                }
+                None => {
+                    self.tasks.push(Task::Arg(call));
+                    self.push_operand(arg);
+                }
+            }
+        } else {
+            if f.memo {
+                let key: Vec<u64> = f
+                    .args
+                    .iter()
+                    .filter_map(|param| call.scope.get(param_name(param)?))
+                    .map(|value| value.to_bits())
+                    .collect();
+                if let Some(value) = self
+                    .memo
+                    .get(&(f as *const FnExpr))
+                    .and_then(|results| results.get(&key))
+                {
+                    self.values.push(*value);
+                    return Ok(());
+                }
+                call.memo_key = Some(key);
+            }
+            self.scopes.push(std::mem::take(&mut call.scope));
+            self.tasks.push(Task::Leave(call));
+            self.push_body(&f.body);
+        }
+        Ok(())
+    }
+
+    /// Drop the scope of a function and copy its reference parameters back to the caller's variables.
+    fn leave(&mut self, references: &[(&String, &String)]) {
+        let scope = self.scopes.pop().expect("No variable scopes found");
+        for (param, name) in references {
+            if let (Some(value), Some(n)) = (scope.get(*param), lookup(self.scopes, name)) {
+                *n = *value;
+            }
+        }
+    }
+
+    /// Evaluate the operands of `node` and then finish it. Simple operands are evaluated right away; otherwise
+    /// the tasks for `node` and its operands are pushed, so the operands are evaluated in order before it.
+    fn finish_after(
+        &mut self,
+        node: &'n Node,
+        operands: &[&'n [Node]],
+    ) -> Result<(), RuntimeError> {
+        let base = self.values.len();
+        for operand in operands {
+            match self.operand(operand) {
+                Some(value) => self.values.push(value),
+                None => {
+                    self.values.truncate(base);
+                    self.tasks.push(Task::Finish(node));
+                    for operand in operands.iter().rev() {
+                        self.push_operand(operand);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        self.finish(node)
+    }
+
+    /// Evaluate the condition of a while loop, then run its body if the condition is true.
+    fn check_while(&mut self, e: &'n WhileExpr, last: f64) {
+        match self.operand(&e.condition) {
+            Some(condition) => self.run_while(e, condition, last),
+            None => {
+                self.tasks.push(Task::While { e, last });
+                self.push_operand(&e.condition);
+            }
+        }
+    }
+
+    fn run_while(&mut self, e: &'n WhileExpr, condition: f64, last: f64) {
+        if num_to_bool(condition) {
+            self.tasks.push(Task::WhileBody(e));
+            self.push_body(&e.body);
+        } else {
+            self.values.push(last);
+        }
+    }
+
+    fn run_if(&mut self, e: &'n IfExpr, condition: f64) {
+        if num_to_bool(condition) {
+            self.push_body(&e.body);
+        } else {
+            self.push_body(&e.else_body);
+        }
+    }
+
+    /// The value of a simple node (a number, a variable, or an operator on those), which can be computed
+    /// without any tasks. Returns `None` for other nodes, and for undefined variables so their error is
+    /// reported by the tasks.
+    fn simple(&mut self, node: &Node) -> Option<f64> {
+        let leaf = |scopes: &mut Vec<HashMap<String, f64>>, nodes: &[Node]| match nodes {
+            [Node::Number(n)] => Some(n.0),
+            [Node::BoolLit(b)] => Some(bool_to_num(*b)),
+            [Node::Variable(v) | Node::Reference(v)] => lookup(scopes, v).map(|n| *n),
+            _ => None,
+        };
+        match node {
+            Node::BinaryExpr(e) => {
+                let lhs = leaf(self.scopes, &e.lhs)?;
+                let rhs = leaf(self.scopes, &e.rhs)?;
+                Some(binary(&e.op, lhs, rhs))
+            }
+            Node::UnaryExpr(e) => Some(unary(e.op, leaf(self.scopes, &e.operand)?)),
+            _ => leaf(self.scopes, std::slice::from_ref(node)),
+        }
+    }
+
+    /// The value of an operand if it is a single simple node.
+    fn operand(&mut self, nodes: &[Node]) -> Option<f64> {
+        match nodes {
+            [node] => self.simple(node),
+            _ => None,
+        }
+    }
+
+    fn push_body(&mut self, nodes: &'n [Node]) {
+        self.tasks.push(Task::Body {
+            nodes,
+            next: 0,
+            return_val: None,
+            last_val: 0.0,
+            operand: false,
+            returned: false,
+            valued: false,
+        });
+    }
+
+    /// Push an operand of a node. Unlike a statement, a loop that is an operand has a value.
+    fn push_operand(&mut self, nodes: &'n [Node]) {
+        self.tasks.push(Task::Body {
+            nodes,
+            next: 0,
+            return_val: None,
+            last_val: 0.0,
+            operand: true,
+            returned: false,
+            valued: false,
+        });
+    }
+
+    fn pop(&mut self) -> f64 {
+        // an empty stack is a bug in the interpreter, so it panics rather than exits
+        self.values.pop().expect("No value on the stack")
+    }
+
+    fn variable(&mut self, name: &str) -> Result<f64, RuntimeError> {
+        match lookup(self.scopes, name) {
+            Some(n) => Ok(*n),
+            None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    fn function(&self, name: &str) -> Result<&'n FnExpr, RuntimeError> {
+        let functions = self.functions;
+        match self.defined.get(name) {
+            Some(f) => Ok(f),
+            None => functions.get(name).ok_or_else(|| {
+                let scopes = [self.scopes.first(), self.scopes.last()];
+                if scopes
+                    .into_iter()
+                    .flatten()
+                    .any(|scope| scope.contains_key(name))
+                {
+                    RuntimeError::NotCallable(name.to_string())
+                } else {
+                    RuntimeError::UndefinedFunction(name.to_string())
+                }
+            }),
+        }
+    }
+}
+
+/// The syntax of the source passed to [`Compile::from_source`].
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Syntax {
+    /// Reverse Polish Notation (e.g. `+ 1 * 2 3`). This is the default.
+    #[default]
+    Rpn,
+    /// Infix notation with operator precedence (e.g. `1 + 2 * 3`). See [`parse_infix`].
+    Infix,
+}
+
+/// How the interpreter prints numbers.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum FloatFormat {
+    /// Rust's `{}` formatting, without an exponent or a trailing `.0` (e.g. `1`, `0.1`). This is the default.
+    #[default]
+    Default,
+    /// The shortest representation that round-trips, from Ryū (e.g. `1.0`, `0.1`, `1e21`). It is the same
+    /// on every platform, which makes it suited to comparing output against golden files.
+    Shortest,
+    /// A fixed number of digits after the decimal point (e.g. `Fixed(2)` prints `0.10`).
+    Fixed(usize),
+    /// The raw IEEE 754 bits in hex (e.g. `0x3ff0000000000000` for `1`), for diagnosing precision issues.
+    Bits,
+}
+
+impl FloatFormat {
+    /// Format a number in this format.
+    pub fn format(&self, value: f64) -> String {
+        match self {
+            Self::Default => value.to_string(),
+            Self::Shortest => ryu::Buffer::new().format(value).to_string(),
+            Self::Fixed(precision) => format!("{value:.precision$}"),
+            Self::Bits => format!("{:#018x}", value.to_bits()),
+        }
+    }
+}
+
+/// Parse source written in the given syntax into an AST.
+pub fn parse_source(source: &str, syntax: Syntax) -> Result<Vec<Node>, ParseError> {
+    let source = join_lines(source);
+    match syntax {
+        Syntax::Rpn => parse(&mut lex(&source), &mut HashMap::new()),
+        Syntax::Infix => parse_infix(&source),
+    }
+}
+
+/// Turn a program into one that runs its `entry` function. The program may only define functions, and
+/// a `return` of the call to `entry` (which must take no parameters) is added after the definitions.
+pub fn with_entry(mut nodes: Vec<Node>, entry: &str) -> Result<Vec<Node>, ParseError> {
+    let mut found = false;
+    for node in &nodes {
+        match node {
+            Node::FnExpr(e) if e.name == entry => found = e.args.is_empty(),
+            Node::FnExpr(_) => (),
+            _ => return Err(ParseError::TopLevelStatement),
+        }
+    }
+    if !found {
+        return Err(ParseError::InvalidEntry(entry.to_string()));
+    }
+
+    nodes.push(Node::ReturnExpr(ReturnExpr {
+        value: vec![Node::FnCallExpr(FnCallExpr {
+            name: entry.to_string(),
+            args: Vec::new(),
+        })],
+    }));
+    Ok(nodes)
+}
+
+/// Replace each call that passes only numbers to a pure function with its result, evaluated by the
+/// interpreter (e.g. `sum (2 3)` becomes `5`). A function is pure if it only reads and changes its parameters
+/// and its own variables, does not print, does not take references, does not use `clock`, `rand` or `randint`,
+/// and only calls pure functions. It must also end in a `return` with no other `return` in it, and only use
+/// `if`, loops and `do` blocks as statements, since the LLVM backend, which folds calls before generating
+/// code, gives those a different value. A call whose evaluation fails, or takes more than [`FOLD_STEPS`]
+/// steps (e.g. because it never returns), is left as it is.
+pub fn fold_constant_calls(nodes: &mut [Node]) {
+    fold_calls(nodes, &mut HashMap::new());
+}
+
+/// Fold the calls in a body, adding the functions it defines to `functions` in order.
+fn fold_calls(nodes: &mut [Node], functions: &mut HashMap<String, FnExpr>) {
+    for node in nodes {
+        if let Node::FnCallExpr(call) = node {
+            if let Some(folded) = fold_call(call, functions) {
+                *node = folded;
+                continue;
+            }
+        }
+        if let Node::FnExpr(e) = node {
+            // the table keeps the unfolded body, which evaluates to the same values
+            functions.insert(e.name.clone(), e.clone());
+        }
+        let (operands, bodies) = children_mut(node);
+        for operand in operands {
+            fold_calls(operand, functions);
+        }
+        for body in bodies {
+            fold_shared(body, functions);
+        }
+    }
+}
+
+/// Fold the calls in a shared body. The body is copied, as it can also be held by a function table.
+fn fold_shared(body: &mut Rc<[Node]>, functions: &mut HashMap<String, FnExpr>) {
+    let mut nodes = body.to_vec();
+    fold_calls(&mut nodes, functions);
+    *body = nodes.into();
+}
+
+/// The most steps of the interpreter folding one call may take, so a call that does not return (e.g. one that
+/// the program never reaches) does not stop compilation.
+pub const FOLD_STEPS: usize = 1_000_000;
+
+/// The constant a call folds to, if it passes only numbers to a pure function and evaluates without errors.
+fn fold_call(call: &FnCallExpr, functions: &HashMap<String, FnExpr>) -> Option<Node> {
+    let f = functions.get(&call.name)?;
+    let constant_args = call.args.iter().all(|arg| matches!(arg, Node::Number(_)));
+    if !constant_args || !is_pure(f, functions, &mut Vec::new()) {
+        return None;
+    }
+    let call = [Node::FnCallExpr(call.clone())];
+    let value = eval_scoped(
+        &call,
+        &mut vec![HashMap::new()],
+        &mut functions.clone(),
+        &mut Printer::new(&mut std::io::sink()),
+        Some(FOLD_STEPS),
+    )
+    .ok()??;
+    Some(match f.return_type {
+        Some(Type::Bool) => Node::BoolLit(num_to_bool(value)),
+        _ => Node::Number(Number(value)),
+    })
+}
+
+/// Whether a function has no side effects and only depends on its arguments. `visiting` holds the functions
+/// being checked, so a recursive call is assumed to be pure.
+fn is_pure(f: &FnExpr, functions: &HashMap<String, FnExpr>, visiting: &mut Vec<String>) -> bool {
+    let mut locals = Vec::new();
+    for param in &f.args {
+        match param {
+            Node::Variable(name) | Node::BindExpr(BindExpr { name, .. }) => {
+                locals.push(name.clone())
+            }
+            _ => return false,
+        }
+    }
+    if !matches!(f.body.last(), Some(Node::ReturnExpr(_)))
+        || !f.body.iter().all(|node| is_plain_statement(node, true))
+    {
+        return false;
+    }
+    collect_locals(&f.body, &mut locals);
+    visiting.push(f.name.clone());
+    let pure = is_pure_body(&f.body, &locals, functions, visiting);
+    visiting.pop();
+    pure
+}
+
+/// Whether the interpreter and the LLVM backend give a statement the same value: a `return` is only at the top
+/// of a function body (`top`), and `if`, loops and `do` blocks are only statements, not operands.
+fn is_plain_statement(node: &Node, top: bool) -> bool {
+    let statements = |body: &[Node]| body.iter().all(|node| is_plain_statement(node, false));
+    match node {
+        Node::ReturnExpr(e) => top && is_plain_operand(&e.value),
+        Node::IfExpr(e) => {
+            is_plain_operand(&e.condition) && statements(&e.body) && statements(&e.else_body)
+        }
+        Node::WhileExpr(e) => is_plain_operand(&e.condition) && statements(&e.body),
+        Node::ForExpr(e) => {
+            [&e.start, &e.end, &e.step]
+                .into_iter()
+                .all(|operand| is_plain_operand(operand))
+                && statements(&e.body)
+        }
+        Node::Block(body) => statements(body),
+        node => children(node).into_iter().all(is_plain_operand),
+    }
+}
+
+/// Whether an operand has no `return`, `if`, loop or `do` block in it. See [`is_plain_statement`].
+fn is_plain_operand(nodes: &[Node]) -> bool {
+    nodes.iter().all(|node| {
+        !matches!(
+            node,
+            Node::ReturnExpr(_)
+                | Node::IfExpr(_)
+                | Node::WhileExpr(_)
+                | Node::ForExpr(_)
+                | Node::Block(_)
+        ) && children(node).into_iter().all(is_plain_operand)
+    })
+}
+
+/// Add the names a body binds with `let` or as a `for` variable to `locals`.
+fn collect_locals(nodes: &[Node], locals: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::BindExpr(e) => locals.push(e.name.clone()),
+            Node::ForExpr(e) => locals.push(e.var.clone()),
+            // a nested function has its own scope
+            Node::FnExpr(_) => continue,
+            _ => (),
+        }
+        for body in children(node) {
+            collect_locals(body, locals);
+        }
+    }
+}
+
+/// Whether a body has no side effects and only reads and changes the variables in `locals`.
+fn is_pure_body(
+    nodes: &[Node],
+    locals: &[String],
+    functions: &HashMap<String, FnExpr>,
+    visiting: &mut Vec<String>,
+) -> bool {
+    nodes.iter().all(|node| {
+        let pure = match node {
+            Node::PrintStdoutExpr(_)
+            | Node::GlobalExpr(_)
+            | Node::FnExpr(_)
+            | Node::FnRef(_)
+            | Node::Reference(_) => false,
+            Node::Variable(name) => locals.contains(name),
+            Node::MutateExpr(e) => locals.contains(&e.name),
+            Node::BuiltinExpr(e) => {
+                !matches!(e.builtin, Builtin::Clock | Builtin::Rand | Builtin::RandInt)
+            }
+            Node::FnCallExpr(call) => {
+                visiting.contains(&call.name)
+                    || functions
+                        .get(&call.name)
+                        .is_some_and(|f| is_pure(f, functions, visiting))
+            }
+            _ => true,
+        };
+        pure && children(node)
+            .into_iter()
+            .all(|body| is_pure_body(body, locals, functions, visiting))
+    })
+}
+
+/// Save a function table (e.g. one filled by [`parse`]) to a JSON file, so the definitions can be
+/// loaded with [`load_functions`] and passed to a later [`parse`] and [`eval`].
+#[cfg(feature = "serde")]
+pub fn save_functions(
+    functions: &HashMap<String, FnExpr>,
+    path: impl AsRef<std::path::Path>,
+) -> std::io::Result<()> {
+    let json = serde_json::to_string(functions)?;
+    std::fs::write(path, json)
+}
+
+/// Load a function table saved with [`save_functions`].
+#[cfg(feature = "serde")]
+pub fn load_functions(
+    path: impl AsRef<std::path::Path>,
+) -> std::io::Result<HashMap<String, FnExpr>> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+pub struct CompileConfig {
+    pub use_jit: bool,
+    pub show_ir: bool,
+    pub optimization_level: u8,
+    pub name: String,
+    pub progress: ProgressBar,
+    pub syntax: Syntax,
+    /// The target triple for AOT compilation. Defaults to the host.
+    pub target: Option<String>,
+    /// The target CPU for AOT compilation, or `native` for the host CPU.
+    pub cpu: String,
+    /// Target features for AOT compilation (e.g. `+avx2,+fma`). With a CPU of `native`, they are added to the
+    /// host's features.
+    pub features: String,
+    /// Link the AOT object file into an executable. If `false`, the object file is written to `<name>.o`.
+    pub link: bool,
+    /// Keep the object files linked into the executable (`output-*.o` next to it) instead of deleting them.
+    pub keep_temps: bool,
+    /// Write each function to its own object file, with one more for `main`, so a build system can reuse the
+    /// objects of unchanged functions. Without linking, they are written to `<name>-<function>.o` and
+    /// `<name>.o`. Functions and globals are only defined in their own object and declared in the others.
+    pub separate_objects: bool,
+    /// Write the module as LLVM bitcode to `<name>.bc` instead of building an object file or executable.
+    pub emit_bitcode: bool,
+    /// The function the program starts in. If set, the top level may only define functions. See [`with_entry`].
+    /// The generated `main` calls it, so the JIT and executables keep starting at `main`.
+    pub entry: Option<String>,
+    /// Allow comparison results to be used as numbers (e.g. `+ > 2 1 5` is `6`). If `false`, such programs
+    /// are rejected by [`check_bool_coercion`] before they run.
+    pub implicit_bool_coercion: bool,
+    /// Let an `if` without an `else` be used as an operand, evaluating to `0` when its condition is false. If
+    /// `false`, such programs are rejected by [`check_if_values`] before they run.
+    pub implicit_else: bool,
+    /// Reject programs that can give a different result on each run (using `clock`, or `rand` and `randint`
+    /// without a [`seed`](CompileConfig::seed)) with [`check_deterministic`] before they run.
+    pub deterministic: bool,
+    /// Buffer the interpreter's printed output in chunks of this many bytes instead of writing every line.
+    /// The buffer is flushed when it is full and when the program ends.
+    pub buffered_output: Option<usize>,
+    /// How the interpreter prints numbers. Compiled programs always print in [`FloatFormat::Default`].
+    pub float_format: FloatFormat,
+    /// Make the interpreter print the value of each top-level statement, not only what `print` prints.
+    pub print_all: bool,
+    /// Seed the generator of the `rand` and `randint` builtins, so every run draws the same numbers. If
+    /// `None`, the generator is seeded from the clock.
+    pub seed: Option<u64>,
+}
+
+impl CompileConfig {
+    pub fn from(use_jit: bool, show_ir: bool) -> Self {
+        Self {
+            use_jit,
+            show_ir,
+            optimization_level: 1,
+            name: String::from("main"),
+            progress: ProgressBar::new(0),
+            syntax: Syntax::Rpn,
+            target: None,
+            cpu: String::from("generic"),
+            features: String::new(),
+            link: true,
+            keep_temps: false,
+            separate_objects: false,
+            emit_bitcode: false,
+            entry: None,
+            implicit_bool_coercion: true,
+            implicit_else: true,
+            deterministic: false,
+            buffered_output: None,
+            float_format: FloatFormat::Default,
+            print_all: false,
+            seed: None,
+        }
+    }
+
+    /// Start building a config. Fields that are not set keep the defaults of [`CompileConfig::from`].
+    pub fn builder() -> CompileConfigBuilder {
+        CompileConfigBuilder::default()
+    }
+}
+
+/// A builder for [`CompileConfig`] (e.g. `CompileConfig::builder().jit(true).optimization_level(2).build()`).
+pub struct CompileConfigBuilder {
+    config: CompileConfig,
+}
+
+impl Default for CompileConfigBuilder {
+    fn default() -> Self {
+        Self {
+            config: CompileConfig::from(false, false),
+        }
+    }
+}
+
+impl CompileConfigBuilder {
+    /// Use the JIT instead of compiling to an executable.
+    pub fn jit(mut self, use_jit: bool) -> Self {
+        self.config.use_jit = use_jit;
+        self
+    }
+
+    /// Print the generated IR.
+    pub fn show_ir(mut self, show_ir: bool) -> Self {
+        self.config.show_ir = show_ir;
+        self
+    }
+
+    /// The optimization level, from 0 (none) to 3 (aggressive).
+    pub fn optimization_level(mut self, level: u8) -> Self {
+        self.config.optimization_level = level;
+        self
+    }
+
+    /// The name of the output executable.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.config.name = name.into();
+        self
+    }
+
+    /// The progress bar that shows each step of compiling.
+    pub fn progress(mut self, progress: ProgressBar) -> Self {
+        self.config.progress = progress;
+        self
+    }
+
+    /// The syntax the source is parsed as.
+    pub fn syntax(mut self, syntax: Syntax) -> Self {
+        self.config.syntax = syntax;
+        self
+    }
+
+    /// The target triple for AOT compilation.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.config.target = Some(target.into());
+        self
+    }
+
+    /// The target CPU for AOT compilation, or `native` for the host CPU.
+    pub fn cpu(mut self, cpu: impl Into<String>) -> Self {
+        self.config.cpu = cpu.into();
+        self
+    }
+
+    /// Target features for AOT compilation (e.g. `+avx2,+fma`). With a CPU of `native`, they are added to the
+    /// host's features.
+    pub fn features(mut self, features: impl Into<String>) -> Self {
+        self.config.features = features.into();
+        self
+    }
+
+    /// Link the object file into an executable.
+    pub fn link(mut self, link: bool) -> Self {
+        self.config.link = link;
+        self
+    }
+
+    /// Keep the object file linked into the executable.
+    pub fn keep_temps(mut self, keep_temps: bool) -> Self {
+        self.config.keep_temps = keep_temps;
+        self
+    }
+
+    /// Write each function to its own object file.
+    pub fn separate_objects(mut self, separate_objects: bool) -> Self {
+        self.config.separate_objects = separate_objects;
+        self
+    }
+
+    /// Write LLVM bitcode to `<name>.bc` instead of an object file or executable.
+    pub fn emit_bitcode(mut self, emit_bitcode: bool) -> Self {
+        self.config.emit_bitcode = emit_bitcode;
+        self
+    }
+
+    /// The function the program starts in.
+    pub fn entry(mut self, entry: impl Into<String>) -> Self {
+        self.config.entry = Some(entry.into());
+        self
+    }
+
+    /// Allow comparison results to be used as numbers.
+    pub fn implicit_bool_coercion(mut self, coerce: bool) -> Self {
+        self.config.implicit_bool_coercion = coerce;
+        self
+    }
+
+    /// Let an `if` without an `else` be used as an operand.
+    pub fn implicit_else(mut self, implicit_else: bool) -> Self {
+        self.config.implicit_else = implicit_else;
+        self
+    }
+
+    /// Reject programs that can give a different result on each run.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.config.deterministic = deterministic;
+        self
+    }
+
+    /// Buffer the interpreter's printed output in chunks of `capacity` bytes.
+    pub fn buffered_output(mut self, capacity: usize) -> Self {
+        self.config.buffered_output = Some(capacity);
+        self
+    }
+
+    /// Hide the progress bar.
+    pub fn quiet(mut self) -> Self {
+        self.config.progress = ProgressBar::hidden();
+        self
+    }
+
+    /// How the interpreter prints numbers.
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.config.float_format = float_format;
+        self
+    }
+
+    /// Make the interpreter print the value of each top-level statement.
+    pub fn print_all(mut self, print_all: bool) -> Self {
+        self.config.print_all = print_all;
+        self
+    }
+
+    /// Seed the generator of `rand` and `randint`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Finish the configuration.
+    pub fn build(self) -> CompileConfig {
+        self.config
+    }
 }
 
 /// The default trait for compiling a language. This is used to compile a language from a specific source.
@@ -490,242 +2906,2567 @@ pub trait Compile {
     /// The output type of the compiler. Varies depending on the compiler.
     type Output;
 
-    /// Compile an AST into the output type.
-    fn from_ast(nodes: Vec<Node>, config: &CompileConfig) -> Self::Output;
+    /// Compile an AST into the output type.
+    fn from_ast(nodes: Vec<Node>, config: &CompileConfig) -> Self::Output;
+
+    /// Compile a string into the output type.
+    fn from_source(source: &str, config: &CompileConfig) -> Self::Output {
+        config.progress.set_message("Lexing source");
+        log::trace!("tokens: {:?}", lex(source).collect::<Vec<_>>());
+        config.progress.inc(1);
+        config.progress.set_message("Parsing tokens");
+
+        let nodes = parse_source(source, config.syntax).log_expect("Error parsing source");
+        log::debug!("ast: {:?}", nodes);
+        if nodes.is_empty() {
+            log::warn!("Source has no statements; the program evaluates to 0");
+        }
+
+        config.progress.inc(1);
+        config.progress.set_message("Evaluating AST");
+        Self::from_ast(nodes, config)
+    }
+
+    /// Compile a file into the output type. Supply the crate-relative path to the file.
+    fn from_file(path: &str, config: &CompileConfig) -> Self::Output {
+        config.progress.set_message("Reading file");
+        let source = std::fs::read_to_string(path).log_expect("Error reading file");
+        config.progress.inc(1);
+        Self::from_source(&source, config)
+    }
+
+    /// Compile source read from any reader (e.g. a socket or a decompressing stream) into the output type.
+    fn from_reader<R: Read>(mut reader: R, config: &CompileConfig) -> Self::Output {
+        config.progress.set_message("Reading source");
+        let mut source = String::new();
+        reader
+            .read_to_string(&mut source)
+            .log_expect("Error reading source");
+        config.progress.inc(1);
+        Self::from_source(&source, config)
+    }
+}
+
+pub type Compiler<'a> = llvm::LLVMCompiler<'a, 'a>;
+
+/// The default interpreter. It can be used through [`Compile`], or as an instance with [`Interpreter::run`]
+/// to evaluate many programs while reusing its variable and function tables.
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    globals: HashMap<String, f64>,
+    functions: HashMap<String, FnExpr>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse and evaluate RPN source. Globals and functions from previous runs stay defined until
+    /// [`Interpreter::clear`] is called, so the source can also refer to functions defined by earlier runs.
+    pub fn run(&mut self, source: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        let nodes = parse(&mut lex(&join_lines(source)), &mut self.functions)?;
+        Ok(self.run_ast(&nodes, &mut std::io::stdout())?)
+    }
+
+    /// Evaluate an already parsed AST like [`Interpreter::run`], writing printed values to `out`. Parsing once
+    /// and running the AST many times keeps parsing out of measurements such as the benchmarks.
+    pub fn run_ast(&mut self, nodes: &[Node], out: &mut dyn Write) -> Result<f64, RuntimeError> {
+        eval_with_output(
+            nodes,
+            &mut self.globals,
+            &mut self.functions,
+            Printer::new(out),
+        )
+    }
+
+    /// Remove all globals and functions. The allocated tables are kept for the next run.
+    pub fn clear(&mut self) {
+        self.globals.clear();
+        self.functions.clear();
+    }
+
+    /// Parse and evaluate source like [`Compile::from_source`], but return errors instead of exiting. See
+    /// [`Interpreter::from_ast_safe`].
+    pub fn from_source_safe(
+        source: &str,
+        config: &CompileConfig,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        Self::from_ast_safe(parse_source(source, config.syntax)?, config)
+    }
+
+    /// Evaluate an AST like [`Compile::from_ast`], but return errors (including failing to write printed
+    /// output) instead of exiting, and turn a panic in the interpreter into [`RuntimeError::InternalPanic`].
+    /// This is a safety net for embedding: the panic hook still reports the panic, and nothing can be caught
+    /// in a build with `panic = "abort"`.
+    pub fn from_ast_safe(
+        nodes: Vec<Node>,
+        config: &CompileConfig,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let nodes = check_config(nodes, config)?;
+        // the tables of a panicked evaluation are dropped with it, so no broken state can be observed
+        match std::panic::catch_unwind(AssertUnwindSafe(|| interpret(&nodes, config))) {
+            Ok(result) => Ok(result?),
+            Err(panic) => {
+                let message = match panic.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => match panic.downcast_ref::<String>() {
+                        Some(message) => message.clone(),
+                        None => String::from("unknown panic"),
+                    },
+                };
+                Err(RuntimeError::InternalPanic(message).into())
+            }
+        }
+    }
+}
+
+impl Compile for Interpreter {
+    type Output = f64;
+
+    // jit is ignored for the interpreter
+    fn from_ast(nodes: Vec<Node>, config: &CompileConfig) -> Self::Output {
+        let nodes = check_config(nodes, config).log_expect("Error parsing source");
+        interpret(&nodes, config).log_expect("Error evaluating AST")
+    }
+}
+
+/// Evaluate a checked AST with fresh tables, printing to stdout as `config` asks.
+fn interpret(nodes: &[Node], config: &CompileConfig) -> Result<f64, RuntimeError> {
+    if let Some(seed) = config.seed {
+        laspa_std::runtime::laspa_seed_rand(seed);
+    }
+    let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+    let printer = |writer| Printer {
+        writer,
+        float_format: config.float_format,
+        print_all: config.print_all,
+    };
+    match config.buffered_output {
+        Some(capacity) => {
+            let mut out = BufWriter::with_capacity(capacity, std::io::stdout());
+            let result = eval_with_output(nodes, &mut globals, &mut functions, printer(&mut out));
+            // flush before a failure exits, so the output printed so far is kept
+            let flushed = out.flush().map_err(|e| RuntimeError::Output(e.to_string()));
+            result.and_then(|value| flushed.map(|_| value))
+        }
+        None => {
+            let mut out = std::io::stdout();
+            eval_with_output(nodes, &mut globals, &mut functions, printer(&mut out))
+        }
+    }
+}
+
+/// Run `source` with the [`Interpreter`] and panic if it fails or does not return `expected` (which can be
+/// NaN). The panic message includes the source and both values, or the error.
+#[track_caller]
+pub fn run_expect(source: &str, expected: f64) {
+    let actual = match Interpreter::from_source_safe(source, &CompileConfig::from(false, false)) {
+        Ok(actual) => actual,
+        Err(e) => panic!(
+            "läspa program failed\n  expected: {expected}\n     error: {e}\n    source:\n{source}"
+        ),
+    };
+    if actual != expected && !(actual.is_nan() && expected.is_nan()) {
+        panic!(
+            "läspa program returned the wrong value\n  expected: {expected}\n    actual: {actual}\n    source:\n{source}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Comparisons and their expected results, shared by the interpreter and LLVM tests.
+    const COMPARISONS: [(&str, f64); 16] = [
+        ("> -1 -2", 1.0),
+        ("< -1 -2", 0.0),
+        ("> -2 -1", 0.0),
+        ("< -2 -1", 1.0),
+        ("== -1.5 -1.5", 1.0),
+        ("> 0 -1", 1.0),
+        ("< -1 0", 1.0),
+        ("> 0 -0", 0.0),
+        ("< -0 0", 0.0),
+        ("== -0 0", 1.0),
+        ("> / 0 0 1", 0.0),
+        ("< / 0 0 1", 0.0),
+        ("> 1 / 0 0", 0.0),
+        ("< 1 / 0 0", 0.0),
+        ("== / 0 0 / 0 0", 0.0),
+        ("< / -1 0 -1", 1.0),
+    ];
+
+    #[test]
+    fn parse_number() {
+        assert_eq!(Number::new("1.0").log_expect(""), Number(1.0));
+        assert_eq!(Number::new("4").log_expect(""), Number(4.0));
+    }
+
+    #[test]
+    fn parse_add() {
+        assert_eq!(Op::new("+"), Ok(Op::Add));
+    }
+
+    #[test]
+    fn parse_sub() {
+        assert_eq!(Op::new("-"), Ok(Op::Sub));
+    }
+
+    #[test]
+    fn parse_mul() {
+        assert_eq!(Op::new("*"), Ok(Op::Mul));
+    }
+
+    #[test]
+    fn parse_div() {
+        assert_eq!(Op::new("/"), Ok(Op::Div));
+    }
+
+    #[test]
+    fn parse_gt() {
+        assert_eq!(Op::new(">"), Ok(Op::Gt));
+    }
+
+    #[test]
+    fn parse_lt() {
+        assert_eq!(Op::new("<"), Ok(Op::Lt));
+    }
+
+    #[test]
+    fn euclidean_mod() {
+        assert_eq!(Op::new("mod"), Ok(Op::EuclidMod));
+        run_expect("return % -7 3", -1.0);
+        run_expect("return mod -7 3", 2.0);
+        run_expect("return mod 7 -3", 1.0);
+        run_expect("return mod -7 -3", 2.0);
+        run_expect("return mod 7.5 2", 1.5);
+    }
+
+    #[test]
+    fn if_without_else() {
+        let value = |a| format!("let a {a}\nlet b 2\nlet x if > a b\na\nend\nreturn x");
+        run_expect(&value(3), 3.0);
+        run_expect(&value(1), 0.0);
+
+        let strict = CompileConfig::builder().implicit_else(false).build();
+        let error = Interpreter::from_source_safe(&value(1), &strict).unwrap_err();
+        assert_eq!(error.to_string(), ParseError::IfWithoutElse.to_string());
+
+        for source in [
+            "let x 0\nif > 1 2\n:= x 1\nend\nreturn x",
+            "let x if > 1 2\n1\nelse\n2\nend\nreturn x",
+        ] {
+            let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            assert_eq!(check_if_values(&nodes), Ok(()));
+        }
+        let nodes = parse_source("print + 1 do\nif > 1 2\n1\nend\nend", Syntax::Rpn).log_expect("");
+        assert_eq!(check_if_values(&nodes), Err(ParseError::IfWithoutElse));
+    }
+
+    #[test]
+    fn bool_equality() {
+        for (source, expected) in [
+            ("return == > 3 1 < 1 3", 1.0),
+            ("return == > 3 1 > 1 3", 0.0),
+            ("return == < 3 1 > 1 3", 1.0),
+            ("let x 0\nif == > 3 1 < 1 3\n:= x 1\nend\nreturn x", 1.0),
+        ] {
+            run_expect(source, expected);
+            let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            assert_eq!(check_bool_coercion(&nodes), Ok(()));
+        }
+    }
+
+    #[test]
+    fn loop_value() {
+        run_expect(
+            "let i 0\nlet total while < i 5\n:= i + i 1\n* i i\nend\nreturn total",
+            25.0,
+        );
+        run_expect("let total for i 0 4\n* i 10\nend\nreturn total", 30.0);
+        run_expect("let total while < 1 0\n5\nend\nreturn total", 0.0);
+        run_expect("return + 1 for i 0 3\ni\nend", 3.0);
+        // as a statement, a loop is still skipped for the program's value
+        run_expect("let x 7\nfor i 0 3\ni\nend", 7.0);
+        // also when it is the only statement of a function
+        run_expect("fn f ()\nfor i 0 3\ni\nend\nend\nreturn + 1 f ()", 1.0);
+    }
+
+    #[test]
+    fn operator_nesting() {
+        // an operator's operands are the complete expressions that follow it, from left to right
+        for (source, expected) in [
+            ("- 10 - 3 2", 9.0),
+            ("- - 10 3 2", 5.0),
+            ("/ / 100 2 5", 10.0),
+            ("/ 100 / 10 5", 50.0),
+            ("+ * 2 3 4", 10.0),
+            ("* 2 + 3 4", 14.0),
+            ("- * 2 3 / 8 4", 4.0),
+            ("% - 10 3 4", 3.0),
+            ("ipow - 5 3 2", 4.0),
+            ("- ipow 2 3 1", 7.0),
+            ("neg - 1 2", 1.0),
+            ("- neg 1 2", -3.0),
+        ] {
+            run_expect(&format!("return {source}"), expected);
+        }
+
+        let number = |n| vec![Node::Number(Number(n))];
+        assert_eq!(
+            parse_source("- 10 - 3 2", Syntax::Rpn),
+            Ok(vec![Node::BinaryExpr(BinaryExpr {
+                op: Op::Sub,
+                lhs: number(10.0),
+                rhs: vec![Node::BinaryExpr(BinaryExpr {
+                    op: Op::Sub,
+                    lhs: number(3.0),
+                    rhs: number(2.0),
+                })],
+            })])
+        );
+        assert_eq!(
+            parse_source("/ / 100 2 5", Syntax::Rpn),
+            Ok(vec![Node::BinaryExpr(BinaryExpr {
+                op: Op::Div,
+                lhs: vec![Node::BinaryExpr(BinaryExpr {
+                    op: Op::Div,
+                    lhs: number(100.0),
+                    rhs: number(2.0),
+                })],
+                rhs: number(5.0),
+            })])
+        );
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        run_expect("let счёт 5\n:= счёт + счёт 1\nreturn счёт", 6.0);
+        run_expect("let λ 2\nreturn * λ 3", 6.0);
+        run_expect("fn café (x)\nreturn * x 2\nend\nreturn café (4)", 8.0);
+        run_expect("fn f (ñ y_2)\nreturn - ñ y_2\nend\nreturn f (3 1)", 2.0);
+        run_expect(
+            "let total 0\nfor ü 0 3\n:= total + total ü\nend\nreturn total",
+            3.0,
+        );
+        assert_eq!(
+            Interpreter::from_source(
+                "let счёт = 5\nсчёт := счёт + 1\nreturn счёт",
+                &CompileConfig {
+                    syntax: Syntax::Infix,
+                    ..CompileConfig::from(false, false)
+                }
+            ),
+            6.0
+        );
+
+        // a name that could not be read back is rejected where it is bound
+        for name in ["5ä", "inf", "NaN", "x.y", "счёт!"] {
+            assert_eq!(
+                parse_source(&format!("let {name} 1"), Syntax::Rpn),
+                Err(ParseError::UnexpectedToken(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn chained_mutation() {
+        run_expect("let x 0\nlet y 0\n:= x := y 5\nreturn + x y", 10.0);
+        run_expect("let a 0\nlet b 0\n:= a + 1 := b 2\nreturn * a b", 6.0);
+        // the innermost assignment's value is the whole chain's value
+        run_expect("let x 0\nlet y 0\nlet z := x := y 4\nreturn z", 4.0);
+    }
+
+    #[test]
+    fn unit_value() {
+        let value = |source| {
+            let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            eval_value(&nodes, &mut HashMap::new(), &mut HashMap::new())
+        };
+        assert_eq!(value("print 5"), Ok(Value::Unit));
+        assert_eq!(value("fn f (x); return x; end"), Ok(Value::Unit));
+        assert_eq!(value("let x 5\nprint x"), Ok(Value::Number(5.0)));
+        assert_eq!(value("print 5\nreturn 0"), Ok(Value::Number(0.0)));
+        assert_eq!(
+            value("let x 0\nwhile < x 3\n:= x + x 1\nreturn * x 2\nend\nprint x"),
+            Ok(Value::Number(6.0))
+        );
+        assert_eq!(value("for i 0 3\nreturn i\nend"), Ok(Value::Number(2.0)));
+        assert_eq!(
+            value("fn f ()\nfor i 0 3\nreturn == i 2\nend\nend\nf ()"),
+            Ok(Value::Number(1.0))
+        );
+        assert_eq!(value("for i 0 3\nprint i\nend"), Ok(Value::Unit));
+
+        for (source, op) in [
+            ("+ print 1 2", "+"),
+            ("neg do\nwhile < 1 0\nend\nend", "neg"),
+            ("sqrt print 4", "sqrt"),
+        ] {
+            assert_eq!(
+                value(source),
+                Err(RuntimeError::UnitOperand(op.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_names() {
+        assert_eq!(
+            parse_source("let if 5", Syntax::Rpn),
+            Err(ParseError::ReservedName("if".to_string()))
+        );
+        assert_eq!(
+            parse_source("fn return (x)\nreturn x\nend", Syntax::Rpn),
+            Err(ParseError::ReservedName("return".to_string()))
+        );
+        for (source, name) in [
+            ("let print 5", "print"),
+            ("global end 1", "end"),
+            (":= else 2", "else"),
+            ("for while 0 3\nend", "while"),
+            ("let + 1", "+"),
+            ("let sqrt 2", "sqrt"),
+            ("fn clock ()\nreturn 1\nend", "clock"),
+        ] {
+            assert_eq!(
+                parse_source(source, Syntax::Rpn),
+                Err(ParseError::ReservedName(name.to_string()))
+            );
+        }
+        assert_eq!(
+            parse_infix("let while = 1"),
+            Err(ParseError::ReservedName("while".to_string()))
+        );
+        // names that only start with a keyword are fine
+        run_expect("let printed 2\nlet iffy 3\nreturn + printed iffy", 5.0);
+    }
+
+    #[test]
+    fn bool_literals() {
+        run_expect("if true\nreturn 1\nend", 1.0);
+        run_expect("if false\nreturn 1\nelse\nreturn 2\nend", 2.0);
+        run_expect("return + true 1", 2.0);
+
+        let value = |source| {
+            let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            eval_value(&nodes, &mut HashMap::new(), &mut HashMap::new())
+        };
+        assert_eq!(value("let b false"), Ok(Value::Bool(false)));
+        assert_eq!(value("return == true > 2 1"), Ok(Value::Bool(true)));
+        assert_eq!(value("let b true\n+ b 1"), Ok(Value::Number(2.0)));
+
+        let nodes = parse_source("return + true 1", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            check_bool_coercion(&nodes),
+            Err(ParseError::BoolAsNumber("+".to_string()))
+        );
+        for source in ["let true 1", ":= false 0", "fn true ()", "for false 0 3"] {
+            assert!(matches!(
+                parse_source(source, Syntax::Rpn),
+                Err(ParseError::ReservedName(_))
+            ));
+        }
+        assert_eq!(
+            parse_source("fn f (true)", Syntax::Rpn),
+            Err(ParseError::InvalidArgs("true".to_string()))
+        );
+        let nodes = parse_source("let b not false", Syntax::Rpn).log_expect("");
+        assert_eq!(unparse(&nodes), "let b not false\n");
+        assert_eq!(
+            parse_infix("return true"),
+            Ok(vec![Node::ReturnExpr(ReturnExpr {
+                value: vec![Node::BoolLit(true)]
+            })])
+        );
+    }
+
+    #[test]
+    fn logical_ops() {
+        let source = "fn inrange (x)\nif and > x 0 < x 10\nreturn 1\nelse\nreturn 0\nend\nend\nlet a inrange (5)\nlet b inrange (10)\nlet c inrange (-1)\nlet d 0\nif or == b 1 == a 1\n:= d 1\nend\nreturn + + * 1000 a * 100 b + * 10 c d";
+        run_expect(source, 1001.0);
+        run_expect("return + and 2 0 or 0 -1", 1.0);
+
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        assert_eq!(check_bool_coercion(&nodes), Ok(()));
+        let nodes = parse_source("return + and 1 1 2", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            check_bool_coercion(&nodes),
+            Err(ParseError::BoolAsNumber("+".to_string()))
+        );
+
+        let mut config = CompileConfig::from(false, false);
+        config.syntax = Syntax::Infix;
+        assert_eq!(
+            Interpreter::from_source("return 0 > 1 or 1 < 2 and 3 == 3", &config),
+            1.0
+        );
+    }
+
+    #[test]
+    fn op_as_str_round_trip() {
+        for op in [
+            Op::Add,
+            Op::Sub,
+            Op::Mul,
+            Op::Div,
+            Op::Gt,
+            Op::Lt,
+            Op::Mod,
+            Op::Eqt,
+            Op::EuclidMod,
+            Op::And,
+            Op::Or,
+        ] {
+            assert_eq!(Op::new(op.as_str()), Ok(op.clone()));
+            assert_eq!(op.to_string(), op.as_str());
+        }
+    }
+
+    #[test]
+    fn parse_op_from_str() {
+        assert_eq!("+".parse::<Op>(), Ok(Op::Add));
+        assert_eq!(Op::try_from("=="), Ok(Op::Eqt));
+        assert_eq!(
+            "&".parse::<Op>(),
+            Err(ParseError::InvalidOperator("&".to_string()))
+        );
+        assert_eq!("-2.5".parse::<Number>(), Ok(Number(-2.5)));
+        assert_eq!(
+            "2.5.1".parse::<Number>(),
+            Err(ParseError::InvalidNumber("2.5.1".to_string()))
+        );
+    }
+
+    #[test]
+    fn config_builder() {
+        let config = CompileConfig::builder()
+            .jit(true)
+            .optimization_level(2)
+            .name("out")
+            .build();
+        assert!(config.use_jit);
+        assert_eq!(config.optimization_level, 2);
+        assert_eq!(config.name, "out");
+
+        assert!(!config.show_ir);
+        assert_eq!(config.syntax, Syntax::Rpn);
+        assert_eq!(config.target, None);
+        assert_eq!(config.cpu, "generic");
+        assert_eq!(config.features, "");
+        assert!(config.link);
+        assert!(!config.keep_temps);
+        assert!(!config.separate_objects);
+        assert!(!config.emit_bitcode);
+        assert_eq!(config.entry, None);
+        assert!(config.implicit_bool_coercion);
+        assert!(config.implicit_else);
+        assert!(!config.deterministic);
+        assert_eq!(config.buffered_output, None);
+        assert_eq!(config.float_format, FloatFormat::Default);
+        assert!(!config.print_all);
+        assert_eq!(config.seed, None);
+
+        assert!(CompileConfig::builder()
+            .quiet()
+            .build()
+            .progress
+            .is_hidden());
+    }
+
+    #[test]
+    fn invalid_operator() {
+        assert_eq!(
+            parse_source("return + 1 & 2", Syntax::Rpn),
+            Err(ParseError::InvalidOperator("&".to_string()))
+        );
+        assert_eq!(
+            parse_source("let 2x 1; return 2x", Syntax::Rpn),
+            Err(ParseError::UnexpectedToken("2x".to_string()))
+        );
+    }
+
+    #[test]
+    fn line_continuation() {
+        let nodes = parse_source("let x + 1 \\\n    * 2 3\nreturn x", Syntax::Rpn).log_expect("");
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(
+            Ok(nodes),
+            parse_source("let x + 1 * 2 3\nreturn x", Syntax::Rpn)
+        );
+        run_expect("return + 1 \\\r\n2", 3.0);
+        // a `\` that does not end the line is not a continuation
+        assert!(parse_source("return + 1 \\ 2", Syntax::Rpn).is_err());
+    }
+
+    #[test]
+    fn parse_recover_errors() {
+        let source = "let x + 1\nlet y 2\nwhile < y 3\nreturn & y 1\n:= y + y 1\nend\nreturn y";
+        let (nodes, errors) = parse_recover(source);
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::UnexpectedEnd,
+                ParseError::InvalidOperator("&".to_string())
+            ]
+        );
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Ok(3.0)
+        );
+
+        // `parse` reports the first of them
+        assert_eq!(
+            parse_source(source, Syntax::Rpn),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn statements_without_separator() {
+        run_expect("let x 5 let y 6\nreturn + x y", 11.0);
+        run_expect("let x 1 := x + x 1 print x return x", 2.0);
+        run_expect("let x 5 let y + x 1 return * x y", 30.0);
+
+        assert_eq!(
+            parse_source("let x 5 let y 6", Syntax::Rpn),
+            Ok(vec![
+                Node::BindExpr(BindExpr {
+                    name: "x".to_string(),
+                    value: vec![Node::Number(Number(5.0))],
+                }),
+                Node::BindExpr(BindExpr {
+                    name: "y".to_string(),
+                    value: vec![Node::Number(Number(6.0))],
+                }),
+            ])
+        );
+        // statements in a block body can share a line too
+        run_expect(
+            "let x 0\nwhile < x 3\n:= x + x 1 print x\nend\nreturn x",
+            3.0,
+        );
+    }
+
+    #[test]
+    fn parse_long_chain() {
+        // the `return` is one level, so this is as deep as an expression can be
+        let chain = MAX_EXPRESSION_DEPTH - 1;
+        let source = format!("return {}1", "+ 1 ".repeat(chain));
+        let mut ast = parse_source(&source, Syntax::Rpn).unwrap();
+
+        let Some(Node::ReturnExpr(e)) = ast.pop() else {
+            panic!("expected a return");
+        };
+        let mut depth = 0;
+        let mut node = e.value;
+        while let Some(Node::BinaryExpr(e)) = node.pop() {
+            assert_eq!(e.op, Op::Add);
+            depth += 1;
+            node = e.rhs;
+        }
+        assert_eq!(depth, chain);
+
+        let source = format!("return {}1", "+ 1 ".repeat(5000));
+        assert_eq!(
+            parse_source(&source, Syntax::Rpn),
+            Err(ParseError::ExpressionTooDeep)
+        );
+    }
+
+    #[test]
+    fn parse_expr() {
+        let mut tokens = lex("+ * -2 3 - 2 3.5");
+        let nodes = parse(&mut tokens, &mut HashMap::new()).log_expect("");
+        assert_eq!(
+            nodes,
+            vec![Node::BinaryExpr(BinaryExpr {
+                op: Op::Add,
+                lhs: vec![Node::BinaryExpr(BinaryExpr {
+                    op: Op::Mul,
+                    lhs: vec![Node::Number(Number(-2.0))],
+                    rhs: vec![Node::Number(Number(3.0))],
+                })],
+                rhs: vec![Node::BinaryExpr(BinaryExpr {
+                    op: Op::Sub,
+                    lhs: vec![Node::Number(Number(2.0))],
+                    rhs: vec![Node::Number(Number(3.5))],
+                })],
+            }),]
+        )
+    }
+
+    #[test]
+    fn eval_expr() {
+        let mut tokens = lex("return + * -2 3 - 2 3.5");
+        let nodes = parse(&mut tokens, &mut HashMap::new()).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Ok(-7.5)
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let mut tokens = lex("let x + 1");
+        assert_eq!(
+            parse(&mut tokens, &mut HashMap::new()),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn dot_graph() {
+        let nodes = parse_source("+ 1 2", Syntax::Rpn).log_expect("");
+        let dot = ast_to_dot(&nodes);
+        assert!(dot.starts_with("digraph ast {"));
+        assert!(dot.contains("n0 [label=\"BinaryExpr +\"];"));
+        assert!(dot.contains("n1 [label=\"Number 1\"];"));
+        assert!(dot.contains("n2 [label=\"Number 2\"];"));
+        assert!(dot.contains("n0 -> n1 [label=\"lhs\"];"));
+        assert!(dot.contains("n0 -> n2 [label=\"rhs\"];"));
+        assert_eq!(dot.matches("->").count(), 2);
+    }
+
+    #[test]
+    fn constant_calls() {
+        let folded = |source: &str| {
+            let mut nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            fold_constant_calls(&mut nodes);
+            nodes.pop().log_expect("")
+        };
+        let returns = |node| Node::ReturnExpr(ReturnExpr { value: vec![node] });
+        let sum = "fn sum (x y)\nreturn + x y\nend\n";
+        assert_eq!(
+            folded(&format!("{sum}return sum (2 3)")),
+            returns(Node::Number(Number(5.0)))
+        );
+        // a recursive function with a loop and its own variables is still pure
+        let fib =
+            "fn fib (n)\nlet r n\nif > n 1\nlet a fib (- n 1)\n:= r + a fib (- n 2)\nend\nreturn r\nend\n";
+        assert_eq!(
+            folded(&format!("{fib}return fib (10)")),
+            returns(Node::Number(Number(55.0)))
+        );
+        assert_eq!(
+            folded("fn pos (x) : bool\nreturn > x 0\nend\nreturn pos (2)"),
+            returns(Node::BoolLit(true))
+        );
+        assert_eq!(
+            folded(&format!(
+                "{sum}let y 0\nwhile < y 1\n:= y sum (1 2)\nend\nreturn y"
+            )),
+            returns(Node::Variable("y".to_string()))
+        );
+
+        for source in [
+            "fn p (x)\nprint x\nreturn x\nend\nreturn p (1)",
+            "let g 1\nfn h (x)\nreturn + x g\nend\nreturn h (1)",
+            "fn r (x)\nreturn + x rand\nend\nreturn r (1)",
+            "fn q (x)\nprint x\nend\nfn w (x)\nreturn q (x)\nend\nreturn w (1)",
+            "fn sum (x y)\nreturn + x y\nend\nlet a 2\nreturn sum (a 3)",
+            // evaluation fails, so the error is left for the program to report
+            "fn bad (x)\nreturn ipow 2 x\nend\nreturn bad (0.5)",
+            // the interpreter would not return early from the `if`, unlike compiled code
+            "fn f (x)\nif > x 1\nreturn 7\nend\nreturn 8\nend\nreturn f (3)",
+            // compiled code evaluates an `if` used as a value to 0
+            "fn g (x)\nlet y if > x 0\n1\nelse\n2\nend\nreturn y\nend\nreturn g (1)",
+        ] {
+            assert!(
+                matches!(&folded(source), Node::ReturnExpr(e) if matches!(e.value[..], [Node::FnCallExpr(_)])),
+                "{source}"
+            );
+        }
+        // a call that never returns runs out of steps and is left for run time, where it is never reached
+        let mut nodes = parse_source(
+            "fn spin (x)\nwhile 1\n:= x + x 1\nend\nreturn x\nend\nif 0\nlet s spin (1)\nend\nreturn 2",
+            Syntax::Rpn,
+        )
+        .log_expect("");
+        fold_constant_calls(&mut nodes);
+        match &nodes[1] {
+            Node::IfExpr(e) => assert!(matches!(
+                &e.body[..],
+                [Node::BindExpr(BindExpr { value, .. })] if matches!(value[..], [Node::FnCallExpr(_)])
+            )),
+            node => panic!("Expected an if, got {node:?}"),
+        }
+
+        let mut nodes = parse_source(
+            &format!("{sum}let y 0\nwhile < y 1\n:= y sum (1 2)\nend"),
+            Syntax::Rpn,
+        )
+        .log_expect("");
+        fold_constant_calls(&mut nodes);
+        match &nodes[2] {
+            Node::WhileExpr(e) => assert_eq!(
+                e.body[..],
+                [Node::MutateExpr(MutateExpr {
+                    name: "y".to_string(),
+                    value: vec![Node::Number(Number(3.0))]
+                })]
+            ),
+            other => panic!("Expected a loop, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn try_parse_adversarial() {
+        for source in ["(", ")", "fn", "+", "let", "let x (", "fn f (", "+ 1 )"] {
+            assert!(try_parse(source).is_err(), "{source:?} parsed");
+        }
+        for source in ["", "\n;\n", "end"] {
+            assert_eq!(try_parse(source), Ok(Vec::new()));
+        }
+
+        let nested = |depth: usize| "if 1\n".repeat(depth) + &"end\n".repeat(depth);
+        assert!(try_parse(&nested(MAX_BLOCK_DEPTH)).is_ok());
+        assert_eq!(try_parse(&nested(100_000)), Err(ParseError::TooDeep));
+        let operators = |depth: usize| "neg ".repeat(depth) + "1";
+        assert!(try_parse(&operators(MAX_EXPRESSION_DEPTH)).is_ok());
+        assert_eq!(
+            try_parse(&operators(100_000)),
+            Err(ParseError::ExpressionTooDeep)
+        );
+        assert_eq!(
+            try_parse(&("- ".repeat(100_000) + "1")),
+            Err(ParseError::ExpressionTooDeep)
+        );
+
+        let infix = |source: &str| parse_source(source, Syntax::Infix);
+        let nested = |depth: usize| "if 1\n".repeat(depth) + &"end\n".repeat(depth);
+        assert!(infix(&nested(MAX_BLOCK_DEPTH)).is_ok());
+        assert_eq!(infix(&nested(100_000)), Err(ParseError::TooDeep));
+        let chain = |depth: usize| "1".to_string() + &" + 1".repeat(depth);
+        assert!(infix(&chain(MAX_EXPRESSION_DEPTH)).is_ok());
+        assert_eq!(infix(&chain(100_000)), Err(ParseError::ExpressionTooDeep));
+        for source in [
+            "(".repeat(100_000),
+            "-".repeat(100_000) + "1",
+            "f(".repeat(100_000),
+        ] {
+            assert_eq!(infix(&source), Err(ParseError::ExpressionTooDeep));
+        }
+    }
+
+    #[test]
+    fn parse_error_propagates() {
+        fn run(source: &str) -> Result<f64, Box<dyn std::error::Error>> {
+            let nodes = parse(&mut lex(source), &mut HashMap::new())?;
+            Ok(eval(&nodes, &mut HashMap::new(), &mut HashMap::new())?)
+        }
+
+        assert_eq!(run("return + 1 2").log_expect(""), 3.0);
+        assert_eq!(
+            run("fn sum x y").unwrap_err().to_string(),
+            "Invalid function arguments `x y`. Must be in the form (arg1 arg2 ...)"
+        );
+    }
+
+    #[test]
+    fn memo() {
+        let fib = |attribute: &str, n: u32| {
+            let source = format!(
+                "let calls 0\nfn fib (n) {attribute}\n:= calls + calls 1\nlet r n\nif > n 1\nlet a fib (- n 1)\n:= r + a fib (- n 2)\nend\nreturn r\nend\nreturn fib ({n})"
+            );
+            let nodes = parse_source(&source, Syntax::Rpn).log_expect("");
+            let mut globals = HashMap::new();
+            let result = eval(&nodes, &mut globals, &mut HashMap::new()).log_expect("");
+            (result, globals["calls"])
+        };
+
+        // each argument is only evaluated once, instead of once per path through the recursion
+        assert_eq!(fib("#[memo]", 20), (6765.0, 21.0));
+        assert_eq!(fib("", 20), (6765.0, 21891.0));
+        assert_eq!(fib("#[memo]", 35), (9227465.0, 36.0));
+
+        let nodes = parse_source(
+            "fn f (x) #[memo] #[optimize none]; return x; end",
+            Syntax::Rpn,
+        );
+        match nodes.log_expect("").first() {
+            Some(Node::FnExpr(f)) => {
+                assert!(f.memo);
+                assert_eq!(f.optimize, Some(OptimizeHint::None));
+            }
+            _ => panic!("Expected a function"),
+        }
+        assert_eq!(
+            parse_source("fn f (&x) #[memo]; return x; end", Syntax::Rpn),
+            Err(ParseError::InvalidMemo("f".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_optimize_hint() {
+        let nodes = parse_source(
+            "fn hot (x) #[optimize aggressive] : num; return x; end",
+            Syntax::Rpn,
+        );
+        match nodes.log_expect("").first() {
+            Some(Node::FnExpr(f)) => {
+                assert_eq!(f.optimize, Some(OptimizeHint::Aggressive));
+                assert_eq!(f.return_type, Some(Type::Num));
+            }
+            other => panic!("Expected a function, got {other:?}"),
+        }
+        assert_eq!(
+            parse_source("fn hot (x) #[optimize fast]; return x; end", Syntax::Rpn),
+            Err(ParseError::InvalidAttribute("optimize fast".to_string()))
+        );
+    }
+
+    #[test]
+    fn duplicate_param() {
+        assert_eq!(
+            parse_source("fn f (x y x); return x; end", Syntax::Rpn),
+            Err(ParseError::DuplicateParam("x".to_string()))
+        );
+        assert_eq!(
+            parse_source("fn f(x, x)\nreturn x\nend", Syntax::Infix),
+            Err(ParseError::DuplicateParam("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn expression_args() {
+        let source = "fn sum (x y)\nreturn + x y\nend\nlet a sum (+ 1 2 * 2 3)\nlet b sum (< 1 2 5)\nlet c sum (1 sum (2 3))\nreturn + * 100 a + * 10 b c";
+        run_expect(source, 966.0);
+        // a call can be followed by more arguments
+        run_expect(
+            "fn sum (x y)\nreturn + x y\nend\nreturn sum (sum (2 3) 1)",
+            6.0,
+        );
+
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        assert_eq!(parse_source(&unparse(&nodes), Syntax::Rpn), Ok(nodes));
+    }
+
+    #[test]
+    fn not_callable() {
+        let nodes = parse_source("let f 5\nf (1)", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::NotCallable("f".to_string()))
+        );
+
+        let nodes = parse_source("g (1)", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::UndefinedFunction("g".to_string()))
+        );
+    }
+
+    #[test]
+    fn default_params() {
+        let source = "fn greet (name times=1)\nreturn * name times\nend\nlet a greet (5)\nlet b greet (5 3)\nreturn + a b";
+        run_expect(source, 20.0);
+
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        assert_eq!(parse_source(&unparse(&nodes), Syntax::Rpn), Ok(nodes));
+        assert_eq!(
+            parse_source("fn f (x=1 y); return y; end", Syntax::Rpn),
+            Err(ParseError::MissingDefault("y".to_string()))
+        );
+        assert_eq!(
+            parse_source("fn f (x=1); return x; end; f (x=2)", Syntax::Rpn),
+            Err(ParseError::InvalidArgs("(x=2)".to_string()))
+        );
+        // a comparison in the arguments is not a default
+        run_expect(
+            "fn sum (x y)\nreturn + x y\nend\nreturn sum (== 1 1 2)",
+            3.0,
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn save_and_load_functions() {
+        let path = std::env::temp_dir().join("laspa-functions.json");
+        let mut functions = HashMap::new();
+        parse(&mut lex("fn sq (x); return * x x; end"), &mut functions).log_expect("");
+        save_functions(&functions, &path).log_expect("");
+
+        let mut functions = load_functions(&path).log_expect("");
+        std::fs::remove_file(&path).log_expect("");
+        let nodes = parse(&mut lex("return sq (3)"), &mut functions).log_expect("");
+        assert_eq!(eval(&nodes, &mut HashMap::new(), &mut functions), Ok(9.0));
+    }
+
+    #[test]
+    fn runtime_error() {
+        let mut tokens = lex("return + x 1");
+        let nodes = parse(&mut tokens, &mut HashMap::new()).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::UndefinedVariable("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn empty_source() {
+        run_expect("", 0.0);
+        run_expect("  \n\t;  ", 0.0);
+        run_expect("// nothing to see here\n// at all", 0.0);
+        // commented-out code is not run, even when the comment starts with a keyword
+        run_expect("// let x 5\n// print the result\n   // return x", 0.0);
+        run_expect("let x 2 // return 3\nreturn x // + x 1", 2.0);
+    }
+
+    #[test]
+    fn implicit_result() {
+        run_expect("let x 5", 5.0);
+        run_expect("print 5", 0.0);
+        run_expect("let x 5; print x", 5.0);
+        run_expect("let i 0; while < i 3; := i + i 1; end", 0.0);
+        run_expect("let x 7; for i 0 3; print i; end", 7.0);
+        run_expect("+ 1 2; fn f (x); return x; end", 3.0);
+        run_expect("let x do; let y 4; print y; end", 4.0);
+    }
+
+    #[test]
+    fn comparisons() {
+        for (expr, expected) in COMPARISONS {
+            run_expect(&format!("return {expr}"), expected);
+        }
+    }
+
+    #[test]
+    fn interpret() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(Interpreter::from_source("+ * -2 3 - 2 3.5", &config), -7.5);
+    }
+
+    #[test]
+    fn define_variable() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+             let x 1
+         "#,
+                &config
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn variable_arithmetic() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                "let x 2;
+         let y 1;
+         + x y;",
+                &config
+            ),
+            3.0
+        );
+    }
+
+    #[test]
+    fn variable_arithmetic_complex() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                "let x 2;
+         let y 1;
+         let z + x * y 2;
+         z;",
+                &config
+            ),
+            4.0
+        );
+    }
+
+    #[test]
+    fn return_only() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(Interpreter::from_source("+ 2 3;return 1;", &config), 1.0);
+    }
+
+    #[test]
+    fn while_loop() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+         let x 0;
+         // let y 0;
+         
+         while < x 1000
+             let i 0;
+             while < i 100
+                 := x + x 1;
+                 := i + i 1;
+             end
+         end
+         
+         return + x i;
+         "#,
+                &config
+            ),
+            1100.0
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+         let x 0;
+         if < x 1
+             return 1;
+         else
+             return 2;
+         end
+         "#,
+                &config
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn only_if() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 let x 10;
+                 let y 2
+                 
+                 if < x y
+                     return y
+                 end
+                 
+                 return x
+         "#,
+                &config
+            ),
+            10.0
+        );
+    }
+
+    #[test]
+    fn for_loop_step() {
+        run_expect(
+            r#"
+             let s 0
+             for i 0 10 2
+                 := s + s i
+             end
+             return s
+     "#,
+            20.0,
+        );
+    }
+
+    #[test]
+    fn for_loop_negative_step() {
+        run_expect(
+            r#"
+             let s 0
+             for i 5 0 -1
+                 := s + * s 10 i
+             end
+             return s
+     "#,
+            54321.0,
+        );
+    }
+
+    #[test]
+    fn for_loop_integral_counter_is_exact() {
+        run_expect(
+            r#"
+             let n 0
+             for i 0 1000000
+                 := n + n 1
+             end
+             return + * i 10 - n 1000000
+     "#,
+            10000000.0,
+        );
+        // a fractional step accumulates rounding errors, which is why the docs suggest scaling an integer
+        run_expect("let n 0\nfor x 0 1 0.1\n:= n + n 1\nend\nreturn n", 11.0);
+        run_expect(
+            "let n 0\nfor i 0 10\nlet x / i 10\n:= n + n 1\nend\nreturn n",
+            10.0,
+        );
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let depth = 100;
+        let source = format!(
+            "let x 0\n{}:= x + x 1\n{}return x",
+            "if == 0 0\n".repeat(depth),
+            "end\n".repeat(depth)
+        );
+        run_expect(&source, 1.0);
+
+        let nodes = parse_source("fn f (x); return x; end", Syntax::Rpn).log_expect("");
+        match (&nodes[0], &nodes[0].clone()) {
+            (Node::FnExpr(f), Node::FnExpr(g)) => assert!(Rc::ptr_eq(&f.body, &g.body)),
+            _ => panic!("Expected a function"),
+        }
+    }
+
+    #[test]
+    fn function_bodies_are_shared() {
+        let body = "let y 0\n".repeat(1000);
+        let mut functions = HashMap::new();
+        let nodes = parse(
+            &mut lex(&format!("fn f (x)\n{body}return x\nend")),
+            &mut functions,
+        )
+        .log_expect("");
+        match &nodes[0] {
+            Node::FnExpr(f) => {
+                // one in the AST, one in the function table
+                assert_eq!(Rc::strong_count(&f.body), 2);
+                assert!(Rc::ptr_eq(&f.body, &functions["f"].body));
+                let table = functions.clone();
+                assert_eq!(Rc::strong_count(&f.body), 3);
+                drop(table);
+            }
+            _ => panic!("Expected a function"),
+        }
+    }
+
+    #[test]
+    fn builtin_ipow() {
+        run_expect("return ipow 2 16", 65536.0);
+        run_expect("return ipow 2 -2", 0.25);
+        run_expect("let x 3; return + ipow x 0 1", 2.0);
+
+        let config = CompileConfig::from(false, false);
+        for (base, exp) in [(1.5, 7.0), (-3.0, 5.0), (0.9, 40.0), (2.0, -3.0)] {
+            let result = Interpreter::from_source(&format!("return ipow {base} {exp}"), &config);
+            let expected = f64::powf(base, exp);
+            assert!((result - expected).abs() <= 1e-12 * expected.abs());
+        }
+
+        let nodes = parse_source("return ipow 2 2.5", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::InvalidArgument {
+                builtin: Builtin::IPow,
+                value: 2.5
+            })
+        );
+        assert_eq!(
+            Interpreter::from_source(
+                "return ipow(2, 3 + 1)",
+                &CompileConfig {
+                    syntax: Syntax::Infix,
+                    ..CompileConfig::from(false, false)
+                }
+            ),
+            16.0
+        );
+    }
+
+    #[test]
+    fn builtin_rand() {
+        let source = "let a rand\nlet b randint 1 6\nreturn + * a 10 b";
+        let config = CompileConfig::builder().seed(42).build();
+        let first = Interpreter::from_source(source, &config);
+        assert_eq!(Interpreter::from_source(source, &config), first);
+        let other = CompileConfig::builder().seed(43).build();
+        assert_ne!(Interpreter::from_source(source, &other), first);
+
+        let unseeded = CompileConfig::from(false, false);
+        for _ in 0..100 {
+            let r = Interpreter::from_source("return rand", &unseeded);
+            assert!((0.0..1.0).contains(&r));
+            let n = Interpreter::from_source("return randint -2 2", &unseeded);
+            assert!((-2.0..=2.0).contains(&n) && n.fract() == 0.0);
+        }
+        run_expect("return randint 3 3", 3.0);
+
+        let nodes = parse_source("return randint 2 1", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::InvalidArgument {
+                builtin: Builtin::RandInt,
+                value: 1.0
+            })
+        );
+    }
+
+    #[test]
+    fn builtin_clock() {
+        let source = "let a clock\nlet b clock\nreturn - b a";
+        assert!(Interpreter::from_source(source, &CompileConfig::from(false, false)) >= 0.0);
+
+        let config = CompileConfig::builder().deterministic(true).build();
+        let error = Interpreter::from_source_safe(source, &config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            ParseError::NonDeterministic(Builtin::Clock).to_string()
+        );
+
+        let nodes = parse_source("return randint 1 6", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            check_deterministic(&nodes, false),
+            Err(ParseError::NonDeterministic(Builtin::RandInt))
+        );
+        assert_eq!(check_deterministic(&nodes, true), Ok(()));
+    }
+
+    #[test]
+    fn builtin_hypot() {
+        run_expect("return hypot 3 4", 5.0);
+        run_expect("return == hypot 3 4 5", 1.0);
+        run_expect("return hypot -5 12", 13.0);
+        // squaring first would overflow to infinity
+        let config = CompileConfig::from(false, false);
+        let result = Interpreter::from_source("return hypot 3e200 4e200", &config);
+        assert!((result - 5e200).abs() <= 1e-15 * 5e200);
+        run_expect("return dist 1 1 4 5", 5.0);
+        run_expect("let x 2; return dist x x x x", 0.0);
+        assert_eq!(
+            Interpreter::from_source(
+                "return dist(0, 0, hypot(3, 4), 12)",
+                &CompileConfig {
+                    syntax: Syntax::Infix,
+                    ..CompileConfig::from(false, false)
+                }
+            ),
+            13.0
+        );
+    }
+
+    #[test]
+    fn builtin_sqrt() {
+        run_expect("return sqrt 16", 4.0);
+        run_expect("let x 9; return + sqrt x sqrt 0", 3.0);
+        assert!(
+            Interpreter::from_source("return sqrt -1", &CompileConfig::from(false, false)).is_nan()
+        );
+    }
+
+    #[test]
+    fn reference_param() {
+        let source =
+            "fn inc (&x)\n:= x + x 1\nend\nlet y 1\nlet r inc (y)\nlet r inc (y)\nreturn y";
+        run_expect(source, 3.0);
+
+        // only reference parameters are copied back
+        let source =
+            "fn f (&x y)\n:= x y\n:= y 0\nend\nlet a 1\nlet b 2\nlet r f (a b)\nreturn + * a 10 b";
+        run_expect(source, 22.0);
+
+        let nodes =
+            parse_source("fn inc (&x)\n:= x + x 1\nend\nlet r inc (1)", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            eval(&nodes, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::InvalidReference("x".to_string()))
+        );
+        assert_eq!(
+            parse_source("fn f (&x &x)", Syntax::Rpn),
+            Err(ParseError::DuplicateParam("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn entry_function() {
+        let config = CompileConfig::builder().entry("start").build();
+        let source = "fn five ()\nreturn 5\nend\nfn start ()\nlet x five ()\nreturn + x 1\nend";
+        assert_eq!(Interpreter::from_source(source, &config), 6.0);
+
+        let nodes = parse_source("fn start ()\nreturn 5\nend\nprint 1", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            with_entry(nodes, "start"),
+            Err(ParseError::TopLevelStatement)
+        );
+        let nodes = parse_source("fn start (x)\nreturn x\nend", Syntax::Rpn).log_expect("");
+        assert_eq!(
+            with_entry(nodes, "start"),
+            Err(ParseError::InvalidEntry("start".to_string()))
+        );
+        assert_eq!(
+            with_entry(Vec::new(), "start"),
+            Err(ParseError::InvalidEntry("start".to_string()))
+        );
+    }
+
+    #[test]
+    fn bool_coercion() {
+        run_expect("return + > 2 1 5", 6.0);
+        run_expect("return * < 2 1 5", 0.0);
+
+        let strict = CompileConfig::builder()
+            .implicit_bool_coercion(false)
+            .build();
+        let source = "let x 0; if > 2 1; := x 1; end; return == > 2 1 < 1 2";
+        assert_eq!(Interpreter::from_source(source, &strict), 1.0);
+
+        for (source, op) in [
+            ("return + > 2 1 5", "+"),
+            ("return * 5 do == 1 1 end", "*"),
+            (
+                "fn odd (n) : bool\nreturn == % n 2 1\nend\nreturn - 1 odd (3)",
+                "-",
+            ),
+            ("return ipow < 1 2 2", "ipow"),
+        ] {
+            let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+            assert_eq!(
+                check_bool_coercion(&nodes),
+                Err(ParseError::BoolAsNumber(op.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn buffered_output() {
+        let source = "let i 0; while < i 1000; print * i 3; := i + i 1; end";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        let mut unbuffered = Vec::new();
+        let mut buffered = BufWriter::with_capacity(64, Vec::new());
+        for out in [&mut unbuffered as &mut dyn Write, &mut buffered] {
+            let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+            eval_with_output(&nodes, &mut globals, &mut functions, Printer::new(out))
+                .log_expect("");
+        }
+
+        let buffered = buffered.into_inner().log_expect("");
+        assert_eq!(buffered, unbuffered);
+        assert_eq!(
+            String::from_utf8(unbuffered).log_expect("").lines().count(),
+            1000
+        );
+    }
+
+    #[test]
+    fn builtin_rounding() {
+        run_expect("return sign -4", -1.0);
+        run_expect("return sign 0.5", 1.0);
+        run_expect("return sign 0", 0.0);
+        run_expect("return sign -0", 0.0);
+        run_expect("return round 2.5", 3.0);
+        run_expect("return round -2.5", -3.0);
+        run_expect("return round 2.4", 2.0);
+        run_expect("return trunc -2.9", -2.0);
+        run_expect("return trunc 2.9", 2.0);
+        assert!(
+            Interpreter::from_source("return sign / 0 0", &CompileConfig::from(false, false))
+                .is_nan()
+        );
+        // `sign -0` must not be `-0`
+        assert!(
+            Interpreter::from_source("return sign -0", &CompileConfig::from(false, false))
+                .is_sign_positive()
+        );
+    }
+
+    #[test]
+    fn unary_ops() {
+        run_expect("return neg 5", -5.0);
+        run_expect("return neg neg 5", 5.0);
+        run_expect("let x 3; return + 10 neg x", 7.0);
+        run_expect("return not == 1 2", 1.0);
+        run_expect("return not == 1 1", 0.0);
+        run_expect("return not 0", 1.0);
+        run_expect("let x 0; if not > x 1; := x 4; end; return x", 4.0);
+
+        assert_eq!(
+            parse_source("return neg 5", Syntax::Rpn),
+            Ok(vec![Node::ReturnExpr(ReturnExpr {
+                value: vec![Node::UnaryExpr(UnaryExpr {
+                    op: UnaryOp::Neg,
+                    operand: vec![Node::Number(Number(5.0))],
+                })]
+            })])
+        );
+    }
+
+    #[test]
+    fn float_format() {
+        let source = "print 0.1; print 1; print / 1 3; print 1000000000000000000000; print 0.00000015; print -0; print / 1 0";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        let print = |float_format| {
+            let mut out = Vec::new();
+            let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+            let printer = Printer {
+                float_format,
+                ..Printer::new(&mut out)
+            };
+            eval_with_output(&nodes, &mut globals, &mut functions, printer).log_expect("");
+            String::from_utf8(out).log_expect("")
+        };
+
+        assert_eq!(
+            print(FloatFormat::Shortest),
+            "0.1\n1.0\n0.3333333333333333\n1e21\n1.5e-7\n-0.0\ninf\n"
+        );
+        assert_eq!(
+            print(FloatFormat::Default),
+            "0.1\n1\n0.3333333333333333\n1000000000000000000000\n0.00000015\n-0\ninf\n"
+        );
+        assert_eq!(
+            print(FloatFormat::Fixed(2)),
+            "0.10\n1.00\n0.33\n1000000000000000000000.00\n0.00\n-0.00\ninf\n"
+        );
+        assert_eq!(
+            print(FloatFormat::Bits),
+            "0x3fb999999999999a\n0x3ff0000000000000\n0x3fd5555555555555\n0x444b1ae4d6e2ef50\n\
+             0x3e8421f5f40d8376\n0x8000000000000000\n0x7ff0000000000000\n"
+        );
+    }
+
+    #[test]
+    fn print_all() {
+        let source = "fn sq (x) : num\nreturn * x x\nend\n1\nprint 2\nsq (3)\nlet y 4\nwhile < y 6; := y + y 1; end";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        let mut out = Vec::new();
+        let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+        let printer = Printer {
+            print_all: true,
+            ..Printer::new(&mut out)
+        };
+        let result = eval_with_output(&nodes, &mut globals, &mut functions, printer);
+
+        assert_eq!(result, Ok(4.0));
+        // only top-level statements with a value are printed, not the function body or loop body
+        assert_eq!(String::from_utf8(out).log_expect(""), "1\n2\n9\n4\n");
+    }
+
+    #[test]
+    fn print_call() {
+        let source = "fn sum (x y)\nreturn + x y\nend\nprint sum (2 3)\nreturn sum (1 1)";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        let mut out = Vec::new();
+        let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+        let result = eval_with_output(&nodes, &mut globals, &mut functions, Printer::new(&mut out));
 
-    /// Compile a string into the output type.
-    fn from_source(source: &str, config: &CompileConfig) -> Self::Output {
-        config.progress.set_message("Lexing source");
-        let mut tokens = lex(source);
-        log::trace!("tokens: {:?}", lex(source).collect::<Vec<_>>());
-        config.progress.inc(1);
-        config.progress.set_message("Parsing tokens");
+        assert_eq!(result, Ok(2.0));
+        assert_eq!(String::from_utf8(out).log_expect(""), "5\n");
+    }
 
-        let nodes = parse(&mut tokens, &mut HashMap::new());
-        log::debug!("ast: {:?}", nodes);
-        
-        config.progress.inc(1);
-        config.progress.set_message("Evaluating AST");
-        Self::from_ast(nodes, config)
+    #[test]
+    fn operand_order() {
+        let source = "let x 1\nprint - do print 1 1 end do print 2 2 end\nprint + do := x * x 10 x end do := x + x 1 x end";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        let mut out = Vec::new();
+        let (mut globals, mut functions) = (HashMap::new(), HashMap::new());
+        eval_with_output(&nodes, &mut globals, &mut functions, Printer::new(&mut out))
+            .log_expect("");
+
+        assert_eq!(String::from_utf8(out).log_expect(""), "1\n2\n-1\n21\n");
     }
 
-    /// Compile a file into the output type. Supply the crate-relative path to the file.
-    fn from_file(path: &str, config: &CompileConfig) -> Self::Output {
-        config.progress.set_message("Reading file");
-        let source = std::fs::read_to_string(path).log_expect("Error reading file");
-        config.progress.inc(1);
-        Self::from_source(&source, config)
+    #[test]
+    fn unparse_format() {
+        let source = "fn   sum (x y) : num\n  return +   x y\n end\n\n let   z sum (1 2)\nwhile < z 10 ; := z + z 1\nif == z 10\nprint z\n   else\nprint neg z\nend\nend\nfor i 0 10 2; let y do\n + i 1\nend\nend\nfor i 0 3 1\nend\nlet w + do 1 2 end 3\nfn hot (&n) #[optimize aggressive]\n:= n ipow n 2\nend\nprint ipow 2 3";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+
+        let formatted = unparse(&nodes);
+        assert_eq!(
+            formatted,
+            "fn sum (x y) : num
+    return + x y
+end
+let z sum (1 2)
+while < z 10
+    := z + z 1
+    if == z 10
+        print z
+    else
+        print neg z
+    end
+end
+for i 0 10 2
+    let y do
+        + i 1
+    end
+end
+for i 0 3
+end
+let w + do 1 2 end 3
+fn hot (&n) #[optimize aggressive]
+    := n ipow n 2
+end
+print ipow 2 3
+"
+        );
+        assert_eq!(parse_source(&formatted, Syntax::Rpn).log_expect(""), nodes);
+        // formatting is idempotent
+        assert_eq!(
+            unparse(&parse_source(&formatted, Syntax::Rpn).log_expect("")),
+            formatted
+        );
+    }
+
+    #[test]
+    fn function_reference() {
+        let source = "fn sum (x y)\nreturn + x y\nend\nlet f sum\nlet r f (1 2)\nfn twice (x)\nreturn * x 2\nend\nlet f twice\nreturn + r f (r)";
+        let nodes = parse_source(source, Syntax::Rpn).log_expect("");
+        assert_eq!(
+            nodes[1],
+            Node::BindExpr(BindExpr {
+                name: "f".to_string(),
+                value: vec![Node::FnRef("sum".to_string())],
+            })
+        );
+        assert_eq!(
+            Interpreter::from_ast(nodes, &CompileConfig::from(false, false)),
+            9.0
+        );
+
+        let number = vec![Node::BinaryExpr(BinaryExpr {
+            op: Op::Add,
+            lhs: vec![Node::FnRef("sum".to_string())],
+            rhs: vec![Node::Number(Number(1.0))],
+        })];
+        assert_eq!(
+            eval(&number, &mut HashMap::new(), &mut HashMap::new()),
+            Err(RuntimeError::FunctionAsValue("sum".to_string()))
+        );
+    }
+
+    #[test]
+    fn llvm_jit_function_reference() {
+        let config = CompileConfig::from(true, false);
+        let source = "fn sum (x y)\nreturn + x y\nend\nlet f sum\nlet r f (1 2)\nreturn r";
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            3.0
+        );
+    }
+
+    #[test]
+    fn integer_literal_overflow() {
+        for literal in [
+            "99999999999999999999",
+            "-9223372036854775809",
+            "9223372036854775808",
+        ] {
+            assert!(overflows_i64(literal), "{literal}");
+        }
+        for literal in [
+            "9223372036854775807",
+            "-9223372036854775808",
+            "+12",
+            "1e30",
+            "0.5",
+            "-",
+        ] {
+            assert!(!overflows_i64(literal), "{literal}");
+        }
+
+        // an overflowing literal still parses, rounded to the nearest float
+        assert_eq!(Number::new("99999999999999999999"), Ok(Number(1e20)));
+        run_expect("return + 99999999999999999999 1", 1e20);
+    }
+
+    #[test]
+    fn number_approx_eq() {
+        let sum = Number(0.1 + 0.2);
+        assert_ne!(sum, Number(0.3));
+        assert!(sum.approx_eq(&Number(0.3), 1e-12));
+        assert!(!sum.approx_eq(&Number(0.31), 1e-12));
+        assert!(Number(1.0).approx_eq(&Number(1.0), 0.0));
+        assert!(Number(f64::INFINITY).approx_eq(&Number(f64::INFINITY), 0.0));
+        assert!(!Number(f64::NAN).approx_eq(&Number(f64::NAN), f64::INFINITY));
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let source = "fn count (n acc)\nif == n 0\nreturn acc\nelse\nlet m - n 1\nlet a + acc 1\nreturn count (m a)\nend\nend\nreturn count (100000 0)";
+        run_expect(source, 100000.0);
+    }
+
+    #[test]
+    fn function_call() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 fn sum (x y)
+                     return + x y;
+                 end
+ 
+                 let i 10;
+                 let d 2;
+ 
+                 let z sum (i d);
+ 
+                 return z
+         "#,
+                &config
+            ),
+            12.0
+        );
+    }
+
+    #[test]
+    fn collatz_conjecture() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 fn collatz (n)
+                     while > n 1
+                         if == % n 2 0
+                             := n / n 2
+                         else
+                             := n + * 3 n 1
+                         end
+                         print n
+                     end
+                     return n
+                 end
+ 
+                 return collatz (123)
+         "#,
+                &config
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn do_block() {
+        run_expect(
+            r#"
+             let a 1;
+             let b 2;
+             let x 0;
+             := x do let t + a b + t 1 end
+             return x
+     "#,
+            4.0,
+        );
+    }
+
+    #[test]
+    fn do_block_multiline() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 let x do
+                     let t 10
+                     * t 2
+                 end
+                 return + x 1
+         "#,
+                &config
+            ),
+            21.0
+        );
+    }
+
+    #[test]
+    fn function_return_type() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 fn isodd (n) : bool
+                     return == % n 2 1
+                 end
+
+                 let a isodd (3)
+                 let b isodd (4)
+                 return + a b
+         "#,
+                &config
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn let_without_value() {
+        run_expect(
+            r#"
+             let x ; := x 5 ; return x
+     "#,
+            5.0,
+        );
+    }
+
+    #[test]
+    fn global_from_function() {
+        run_expect(
+            r#"
+             fn setcount (x)
+                 global count * x 2
+                 return 0
+             end
+             let r setcount (21)
+             return count
+     "#,
+            42.0,
+        );
+    }
+
+    #[test]
+    fn function_redefinition() {
+        run_expect(
+            r#"
+             fn f (x)
+                 return + x 1
+             end
+             let a f (1)
+             fn f (x)
+                 return * x 10
+             end
+             let b f (1)
+             return + * a 100 b
+     "#,
+            210.0,
+        );
+    }
+
+    #[test]
+    fn interpreter_reuse() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run("let x 2; return * x 3").log_expect(""), 6.0);
+        assert_eq!(interpreter.run("return + x 1").log_expect(""), 3.0);
+
+        interpreter.clear();
+        assert!(interpreter.run("return x").is_err());
+        assert_eq!(interpreter.run("let y 4; return y").log_expect(""), 4.0);
+
+        // functions from earlier runs are known while parsing, so they can be referenced
+        interpreter
+            .run("fn double (x)\nreturn * x 2\nend")
+            .log_expect("");
+        interpreter.run("let g double").log_expect("");
+        assert_eq!(interpreter.run("return g (4)").log_expect(""), 8.0);
+    }
+
+    #[test]
+    fn interpreter_run_ast() {
+        let nodes = parse_source("global n + n 1\nprint n\nreturn n", Syntax::Rpn).log_expect("");
+        let mut interpreter = Interpreter::new();
+        interpreter.run("global n 0").log_expect("");
+
+        let mut out = Vec::new();
+        assert_eq!(interpreter.run_ast(&nodes, &mut out), Ok(1.0));
+        assert_eq!(interpreter.run_ast(&nodes, &mut out), Ok(2.0));
+        assert_eq!(out, b"1\n2\n");
+    }
+
+    #[test]
+    fn interpreter_output_error() {
+        // a writer that fails like a closed stdout
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let nodes = parse_source("print 1", Syntax::Rpn).log_expect("");
+        let result = Interpreter::new().run_ast(&nodes, &mut Closed);
+        assert!(matches!(result, Err(RuntimeError::Output(_))));
+    }
+
+    #[test]
+    fn interpreter_catches_panics() {
+        let config = CompileConfig::from(false, false);
+        // the parser checks the operand count of builtins, so this AST can only be built by hand
+        let nodes = vec![Node::BuiltinExpr(BuiltinExpr {
+            builtin: Builtin::IPow,
+            args: vec![vec![Node::Number(Number(2.0))]],
+        })];
+        let error = Interpreter::from_ast_safe(nodes, &config).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::InternalPanic(_))
+        ));
+
+        assert_eq!(
+            Interpreter::from_source_safe("return + 1 2", &config).log_expect(""),
+            3.0
+        );
+        let error = Interpreter::from_source_safe("return x", &config).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::UndefinedVariable("x".to_string()))
+        );
+        assert!(Interpreter::from_source_safe("+ 1", &config).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected: 4\n    actual: 3")]
+    fn run_expect_mismatch() {
+        run_expect("return + 1 2", 4.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "läspa program failed")]
+    fn run_expect_error() {
+        run_expect("return + x 1", 1.0);
+    }
+
+    #[test]
+    fn run_expect_nan() {
+        run_expect("return / 0 0", f64::NAN);
+    }
+
+    #[test]
+    fn infix_precedence() {
+        let mut config = CompileConfig::from(true, false);
+        config.syntax = Syntax::Infix;
+        assert_eq!(Interpreter::from_source("return 1 + 2 * 3", &config), 7.0);
+        assert_eq!(Interpreter::from_source("return (1 + 2) * 3", &config), 9.0);
+        assert_eq!(Interpreter::from_source("return 10 - 4 - 3", &config), 3.0);
+        assert_eq!(
+            Interpreter::from_source("return -2 * 3 + 1 < 0", &config),
+            1.0
+        );
+        assert_eq!(
+            Interpreter::from_source("return 1 + -7 mod 3", &config),
+            3.0
+        );
+    }
+
+    #[test]
+    fn infix_matches_rpn_ast() {
+        assert_eq!(
+            parse_source("+ * 2 3 - 2 3.5", Syntax::Rpn),
+            parse_source("2 * 3 + (2 - 3.5)", Syntax::Infix)
+        );
+    }
+
+    #[test]
+    fn infix_program() {
+        let mut config = CompileConfig::from(true, false);
+        config.syntax = Syntax::Infix;
+        assert_eq!(
+            Interpreter::from_source(
+                r#"
+                 fn collatz(n)
+                     let steps = 0
+                     while n > 1
+                         if n % 2 == 0
+                             n := n / 2
+                         else
+                             n := 3 * n + 1
+                         end
+                         steps := steps + 1
+                     end
+                     return steps
+                 end
+
+                 // 27 takes a famously long time to reach 1
+                 return collatz(27)
+         "#,
+                &config
+            ),
+            111.0
+        );
+    }
+
+    #[test]
+    fn infix_parse_error() {
+        assert_eq!(
+            parse_infix("let x = (1 + 2"),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_infix("let x = 1 + ) 2"),
+            Err(ParseError::UnexpectedToken(")".to_string()))
+        );
+    }
+
+    #[test]
+    fn read_from_file() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(Interpreter::from_file("examples/test.laspa", &config), 1.0);
+    }
+
+    #[test]
+    fn read_from_reader() {
+        let config = CompileConfig::from(false, false);
+        let reader = std::io::Cursor::new(b"let x 2\nreturn * x 21".to_vec());
+        assert_eq!(Interpreter::from_reader(reader, &config), 42.0);
+        assert_eq!(Interpreter::from_reader(&b"return + 1 2"[..], &config), 3.0);
+    }
+
+    #[test]
+    fn llvm_jit_empty_source() {
+        let config = CompileConfig::from(true, true);
+        for source in [
+            "",
+            "  \n\t;  ",
+            "// nothing to see here\n// at all",
+            "// let x 5\n// print the result\n   // return x",
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                0.0
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_implicit_result() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("let x 5", 5.0),
+            ("let i 0; while < i 3; := i + i 1; end", 0.0),
+            // the loop is a statement, so the program's value is the binding's
+            ("let x 7; for i 0 3; := x + x i; end", 7.0),
+            ("+ 1 2; fn f (x); return x; end", 3.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_unbound_variable() {
+        let config = CompileConfig::from(true, false);
+        for source in [
+            "return x",
+            "let y + x 1",
+            "fn f (a); return b; end; let r f (1)",
+            ":= x 1",
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config),
+                Err("Variable not found")
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_top_level_returns() {
+        let config = CompileConfig::from(true, false);
+        for source in [
+            "return 5 return 6",
+            "return 5; let x 1; return 6",
+            "if == 1 1; return 5; else; return 4; end; return 6",
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                5.0
+            );
+        }
     }
-}
 
-pub type Compiler<'a> = llvm::LLVMCompiler<'a, 'a>;
+    #[test]
+    fn llvm_jit_session() {
+        let session = JitSession::new(CompileConfig::from(true, false));
+        for i in 0..100 {
+            let source = format!("fn sq (x)\nreturn * x x\nend\nreturn + sq ({i}) 1");
+            assert_eq!(session.run(&source), Ok((i * i + 1) as f64));
+        }
+        assert!(session.run("+ 1").is_err());
+    }
 
-/// The default interpreter.
-pub struct Interpreter;
+    #[test]
+    fn llvm_compile_to_object() {
+        let source = "fn square (x)\nreturn * x x\nend\nprint square (3)";
+        let object = compile_to_object(source, &CompileConfig::from(false, false)).log_expect("");
 
-impl Compile for Interpreter {
-    type Output = f64;
+        let magic: &[u8] = if cfg!(target_os = "macos") {
+            &[0xcf, 0xfa, 0xed, 0xfe] // 64-bit Mach-O
+        } else if cfg!(windows) {
+            &[0x64, 0x86] // x86-64 COFF
+        } else {
+            b"\x7fELF"
+        };
+        assert!(object.starts_with(magic));
 
-    // jit is ignored for the interpreter
-    fn from_ast(nodes: Vec<Node>, _config: &CompileConfig) -> Self::Output {
-        eval(&nodes, &mut HashMap::new(), &mut HashMap::new())
+        assert!(compile_to_object("+ 1", &CompileConfig::from(false, false)).is_err());
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn parse_number() {
-        assert_eq!(Number::new("1.0").log_expect(""), Number(1.0));
-        assert_eq!(Number::new("4").log_expect(""), Number(4.0));
+    fn llvm_jit_comparisons() {
+        let config = CompileConfig::from(true, true);
+        for (expr, expected) in COMPARISONS {
+            let source = format!("return {expr}");
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(&source, &config).log_expect(""),
+                expected,
+                "{expr}"
+            );
+        }
     }
 
     #[test]
-    fn parse_add() {
-        assert_eq!(Op::new("+"), Op::Add);
+    fn llvm_jit_operations() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("+ 1 2", &config).log_expect(""),
+            3.0
+        );
     }
 
     #[test]
-    fn parse_sub() {
-        assert_eq!(Op::new("-"), Op::Sub);
+    fn llvm_jit_euclidean_mod() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("return % -7 3", -1.0),
+            ("return mod -7 3", 2.0),
+            ("return mod 7 -3", 1.0),
+            ("return mod -7 -3", 2.0),
+            ("return mod 7.5 2", 1.5),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected,
+                "{source}"
+            );
+        }
     }
 
     #[test]
-    fn parse_mul() {
-        assert_eq!(Op::new("*"), Op::Mul);
+    fn llvm_jit_bind_val() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("let x 10", &config).log_expect(""),
+            10.0
+        );
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("global g 4", &config).log_expect(""),
+            4.0
+        );
     }
 
     #[test]
-    fn parse_div() {
-        assert_eq!(Op::new("/"), Op::Div);
+    fn llvm_jit_variables() {
+        let config = CompileConfig::from(true, false);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("let x 10; + x 2", &config).log_expect(""),
+            12.0
+        );
     }
 
     #[test]
-    fn parse_gt() {
-        assert_eq!(Op::new(">"), Op::Gt);
+    fn llvm_jit_return_in_if() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("let x 3\nif > x 1\nreturn 7\nend\nreturn 8", 7.0),
+            ("let x 0\nif > x 1\nreturn 7\nend\nreturn 8", 8.0),
+            ("if == 1 1\nreturn 5\nend", 5.0),
+            (
+                "let i 0\nwhile < i 10\nif == i 4\nreturn i\nend\n:= i + i 1\nend\nreturn -1",
+                4.0,
+            ),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
     }
 
     #[test]
-    fn parse_lt() {
-        assert_eq!(Op::new("<"), Op::Lt);
+    fn llvm_jit_return() {
+        let config = CompileConfig::from(true, true);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("let x 10; + x 2;return x", &config).log_expect(""),
+            10.0
+        );
     }
 
     #[test]
-    fn parse_expr() {
-        let mut tokens = lex("+ * -2 3 - 2 3.5");
-        let nodes = parse(&mut tokens, &mut HashMap::new());
+    fn llvm_jit_mutate() {
+        let config = CompileConfig::from(true, true);
         assert_eq!(
-            nodes,
-            vec![Node::BinaryExpr(BinaryExpr {
-                op: Op::Add,
-                lhs: vec![Node::BinaryExpr(BinaryExpr {
-                    op: Op::Mul,
-                    lhs: vec![Node::Number(Number(-2.0))],
-                    rhs: vec![Node::Number(Number(3.0))],
-                })],
-                rhs: vec![Node::BinaryExpr(BinaryExpr {
-                    op: Op::Sub,
-                    lhs: vec![Node::Number(Number(2.0))],
-                    rhs: vec![Node::Number(Number(3.5))],
-                })],
-            }),]
-        )
+            llvm::LLVMCompiler::from_source("let x 10;:= x + x 2;return x", &config).log_expect(""),
+            12.0
+        );
     }
 
     #[test]
-    fn eval_expr() {
-        let mut tokens = lex("return + * -2 3 - 2 3.5");
-        let nodes = parse(&mut tokens, &mut HashMap::new());
-        assert_eq!(eval(&nodes, &mut HashMap::new(), &mut HashMap::new()), -7.5);
+    fn llvm_jit_while() {
+        let config = CompileConfig::from(true, true);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(
+                r#"
+            let x 0;
+            // let y 0;
+            
+            while < x 1000
+                let i 0;
+                while < i 100
+                    := x + x 1;
+                    := i + i 1;
+                end
+            end
+            
+            return + x i;
+            "#,
+                &config
+            )
+            .log_expect(""),
+            1100.0
+        );
     }
 
     #[test]
-    fn interpret() {
-        let config = CompileConfig::from(true, false);
-        assert_eq!(Interpreter::from_source("+ * -2 3 - 2 3.5", &config), -7.5);
+    fn llvm_jit_if_else() {
+        let config = CompileConfig::from(true, true);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(
+                r#"
+         let x 0;
+         if < x 1
+             return 1;
+         else
+             return 2;
+         end
+         "#,
+                &config
+            )
+            .log_expect(""),
+            1.0
+        );
     }
 
     #[test]
-    fn define_variable() {
-        let config = CompileConfig::from(true, false);
+    fn llvm_jit_only_if() {
+        let config = CompileConfig::from(true, true);
         assert_eq!(
-            Interpreter::from_source(
+            llvm::LLVMCompiler::from_source(
                 r#"
-             let x 1
+                 let x 10;
+                 let y 2
+                 
+                 if < x y
+                     return y
+                 end
+                 
+                 return x
          "#,
                 &config
-            ),
-            1.0
+            )
+            .log_expect(""),
+            10.0
         );
     }
 
     #[test]
-    fn variable_arithmetic() {
+    fn llvm_jit_logical_condition() {
         let config = CompileConfig::from(true, false);
+        let source = "fn inrange (x)\nif and > x 0 < x 10\nreturn 1\nelse\nreturn 0\nend\nend\nlet a inrange (5)\nlet b inrange (10)\nlet c inrange (-1)\nlet d 0\nif or == b 1 == a 1\n:= d 1\nend\nreturn + + * 1000 a * 100 b + * 10 c d";
         assert_eq!(
-            Interpreter::from_source(
-                "let x 2;
-         let y 1;
-         + x y;",
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            1001.0
+        );
+        assert_eq!(
+            llvm::LLVMCompiler::from_source("return + and 2 0 or 0 -1", &config).log_expect(""),
+            1.0
+        );
+    }
+
+    #[test]
+    fn llvm_jit_for_loop_step() {
+        let config = CompileConfig::from(true, true);
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(
+                r#"
+                 let s 0
+                 for i 0 10 2
+                     := s + s i
+                 end
+                 return s
+         "#,
                 &config
-            ),
-            3.0
+            )
+            .log_expect(""),
+            20.0
         );
     }
 
     #[test]
-    fn variable_arithmetic_complex() {
-        let config = CompileConfig::from(true, false);
+    fn llvm_jit_for_loop_negative_step() {
+        let config = CompileConfig::from(true, true);
         assert_eq!(
-            Interpreter::from_source(
-                "let x 2;
-         let y 1;
-         let z + x * y 2;
-         z;",
+            llvm::LLVMCompiler::from_source(
+                r#"
+                 let s 0
+                 for i 5 0 -1
+                     := s + * s 10 i
+                 end
+                 return s
+         "#,
                 &config
-            ),
-            4.0
+            )
+            .log_expect(""),
+            54321.0
         );
     }
 
     #[test]
-    fn return_only() {
-        let config = CompileConfig::from(true, false);
-        assert_eq!(Interpreter::from_source("+ 2 3;return 1;", &config), 1.0);
+    fn llvm_jit_builtin_ipow() {
+        let config = CompileConfig::from(true, true);
+        for (source, expected) in [
+            ("return ipow 2 16", 65536.0),
+            ("return ipow 2 -2", 0.25),
+            ("let x 3; return + ipow x 0 1", 2.0),
+            ("return ipow 1.5 7", f64::powf(1.5, 7.0)),
+        ] {
+            let result = llvm::LLVMCompiler::from_source(source, &config).log_expect("");
+            assert!((result - expected).abs() <= 1e-12 * expected.abs());
+        }
     }
 
     #[test]
-    fn while_loop() {
+    fn llvm_jit_reference_param() {
         let config = CompileConfig::from(true, false);
+        let source =
+            "fn inc (&x)\n:= x + x 1\nend\nlet y 1\nlet r inc (y)\nlet r inc (y)\nreturn y";
         assert_eq!(
-            Interpreter::from_source(
-                r#"
-         let x 0;
-         // let y 0;
-         
-         while < x 1000
-             let i 0;
-             while < i 100
-                 := x + x 1;
-                 := i + i 1;
-             end
-         end
-         
-         return + x i;
-         "#,
-                &config
-            ),
-            1100.0
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            3.0
         );
     }
 
     #[test]
-    fn if_else() {
+    fn llvm_jit_reference_to_top_level() {
+        // a function can pass a top-level variable on by reference
         let config = CompileConfig::from(true, false);
+        let source = r#"
+            fn inc (&x)
+                := x + x 1
+            end
+            fn twice ()
+                let r inc (y)
+                let r inc (y)
+                return 0
+            end
+            let y 1
+            let r twice ()
+            return y
+        "#;
         assert_eq!(
-            Interpreter::from_source(
-                r#"
-         let x 0;
-         if < x 1
-             return 1;
-         else
-             return 2;
-         end
-         "#,
-                &config
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            3.0
+        );
+    }
+
+    #[test]
+    fn llvm_jit_entry_function() {
+        let config = CompileConfig::builder().jit(true).entry("start").build();
+        let source = "fn start ()\nreturn 5\nend";
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            5.0
+        );
+    }
+
+    #[test]
+    fn llvm_jit_renamed_entry() {
+        // a function called `main` must not be mistaken for the generated entry point
+        let config = CompileConfig::builder().jit(true).entry("start").build();
+        let source = "fn main ()\nreturn 7\nend\nfn start ()\nreturn * 2 main ()\nend";
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            14.0
+        );
+    }
+
+    #[test]
+    fn llvm_jit_bool_literals() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("if true\nreturn 1\nend\nreturn 0", 1.0),
+            ("let b false\nreturn + b 1", 1.0),
+            ("return == true > 2 1", 1.0),
+            ("let done false\n:= done true\nreturn done", 1.0),
+            ("print true\nreturn 2", 2.0),
+            ("return sqrt true", 1.0),
+            ("let n 0\nfor i false 3\n:= n + n 1\nend\nreturn n", 3.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_unicode_identifiers() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("let счёт 5\n:= счёт + счёт 1\nreturn счёт", 6.0),
+            ("fn café (λ)\nreturn * λ 2\nend\nreturn café (4)", 8.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_chained_mutation() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("let x 0\nlet y 0\n:= x := y 5\nreturn + x y", 10.0),
+            ("let a 0\nlet b 0\n:= a + 1 := b 2\nreturn * a b", 6.0),
+            ("let x 0\nlet y 0\nlet z := x := y 4\nreturn z", 4.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_bool_equality() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("return == > 3 1 < 1 3", 1.0),
+            ("return == > 3 1 > 1 3", 0.0),
+            ("return == < 3 1 > 1 3", 1.0),
+            ("let x 0\nif == > 3 1 < 1 3\n:= x 1\nend\nreturn x", 1.0),
+            (
+                "fn pos (x) : bool\nreturn > x 0\nend\nreturn == pos (2) < 0 1",
+                1.0,
             ),
-            1.0
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_bool_coercion() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [("return + > 2 1 5", 6.0), ("return * < 2 1 5", 0.0)] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_builtin_rounding() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("return sign -4", -1.0),
+            ("return sign 0.5", 1.0),
+            ("return sign 0", 0.0),
+            ("return sign -0", 0.0),
+            ("return round 2.5", 3.0),
+            ("return round -2.5", -3.0),
+            ("return trunc -2.9", -2.0),
+        ] {
+            let result = llvm::LLVMCompiler::from_source(source, &config).log_expect("");
+            assert_eq!(result, expected);
+            assert_eq!(result.is_sign_positive(), expected.is_sign_positive());
+        }
+        assert!(
+            llvm::LLVMCompiler::from_source("return sign / 0 0", &config)
+                .log_expect("")
+                .is_nan()
         );
     }
 
     #[test]
-    fn only_if() {
+    fn llvm_jit_return_in_loop() {
         let config = CompileConfig::from(true, false);
+        let source = r#"
+            fn first (n)
+                let i 0
+                while < i n
+                    if > * i i 50
+                        return i
+                    end
+                    := i + i 1
+                end
+                return -1
+            end
+            fn last (n)
+                for i n 0 -1
+                    return i
+                end
+                return -1
+            end
+
+            let a first (100)
+            let b last (7)
+            return + * a 10 b
+        "#;
         assert_eq!(
-            Interpreter::from_source(
-                r#"
-                 let x 10;
-                 let y 2
-                 
-                 if < x y
-                     return y
-                 end
-                 
-                 return x
-         "#,
-                &config
-            ),
-            10.0
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            87.0
         );
     }
 
     #[test]
-    fn function_call() {
+    fn llvm_jit_unary_ops() {
         let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            ("return neg 5", -5.0),
+            ("return neg neg 5", 5.0),
+            ("let x 3; return + 10 neg x", 7.0),
+            ("return not == 1 2", 1.0),
+            ("return not == 1 1", 0.0),
+            ("return not 0", 1.0),
+            ("let x 0; if not > x 1; := x 4; end; return x", 4.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_builtin_sqrt() {
+        let config = CompileConfig::from(true, true);
+        for (source, expected) in [
+            ("return sqrt 16", 4.0),
+            ("let x 9; return + sqrt x sqrt 0", 3.0),
+            ("print sqrt 2; return sqrt 2", f64::sqrt(2.0)),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_builtin_hypot() {
+        let config = CompileConfig::from(true, true);
+        for (source, expected) in [
+            ("return hypot 3 4", 5.0),
+            ("return hypot 3e200 4e200", f64::hypot(3e200, 4e200)),
+            ("return dist 1 1 4 5", 5.0),
+            ("let x 2; return dist x x x x", 0.0),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_jit_function_call() {
+        let config = CompileConfig::from(true, true);
         assert_eq!(
-            Interpreter::from_source(
+            llvm::LLVMCompiler::from_source(
                 r#"
                  fn sum (x y)
                      return + x y;
@@ -739,206 +5480,365 @@ mod tests {
                  return z
          "#,
                 &config
-            ),
+            )
+            .log_expect(""),
             12.0
         );
     }
 
     #[test]
-    fn collatz_conjecture() {
-        let config = CompileConfig::from(true, false);
-        assert_eq!(
-            Interpreter::from_source(
-                r#"
-                 fn collatz (n)
-                     while > n 1
-                         if == % n 2 0
-                             := n / n 2
-                         else
-                             := n + * 3 n 1
-                         end
-                         print n
-                     end
-                     return n
-                 end
- 
-                 return collatz (123)
-         "#,
-                &config
-            ),
-            1.0
-        );
-    }
-
-    #[test]
-    fn read_from_file() {
-        let config = CompileConfig::from(true, false);
-        assert_eq!(Interpreter::from_file("examples/test.laspa", &config), 1.0);
-    }
-
-    #[test]
-    fn llvm_jit_operations() {
-        let config = CompileConfig::from(true, false);
-        assert_eq!(
-            llvm::LLVMCompiler::from_source("+ 1 2", &config).log_expect(""),
-            3.0
-        );
-    }
-
-    #[test]
-    fn llvm_jit_bind_val() {
+    fn llvm_jit_expression_args() {
         let config = CompileConfig::from(true, false);
+        let source = "fn f (x y)\nreturn + * x 10 y\nend\nlet a f (+ 1 2 < 1 2)\nlet b f (1 f (2 3))\nreturn + * 1000 a b";
         assert_eq!(
-            llvm::LLVMCompiler::from_source("let x 10", &config).log_expect(""),
-            0.0
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            31033.0
         );
     }
 
     #[test]
-    fn llvm_jit_variables() {
+    fn llvm_jit_default_params() {
         let config = CompileConfig::from(true, false);
+        let source = "fn greet (name times=1)\nreturn * name times\nend\nlet a greet (5)\nlet b greet (5 3)\nreturn + a b";
         assert_eq!(
-            llvm::LLVMCompiler::from_source("let x 10; + x 2", &config).log_expect(""),
-            12.0
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            20.0
         );
     }
 
     #[test]
-    fn llvm_jit_return() {
+    fn llvm_jit_collatz_conjecture() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
-            llvm::LLVMCompiler::from_source("let x 10; + x 2;return x", &config).log_expect(""),
-            10.0
+            llvm::LLVMCompiler::from_source(
+                r#"
+                fn collatz (n)
+                while > n 1
+                    if == % n 2 0
+                        := n / n 2
+                    else
+                        := n + * 3 n 1
+                    end
+                    // cannot print in llvm jit
+                    // print n
+                end
+                return n
+            end
+            
+            return collatz (123)
+         "#,
+                &config
+            )
+            .log_expect(""),
+            1.0
         );
     }
 
     #[test]
-    fn llvm_jit_mutate() {
+    fn llvm_jit_do_block() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
-            llvm::LLVMCompiler::from_source("let x 10;:= x + x 2;return x", &config).log_expect(""),
-            12.0
+            llvm::LLVMCompiler::from_source(
+                r#"
+                 let a 1;
+                 let b 2;
+                 let x 0;
+                 := x do let t + a b + t 1 end
+                 return x
+         "#,
+                &config
+            )
+            .log_expect(""),
+            4.0
         );
     }
 
     #[test]
-    fn llvm_jit_while() {
+    fn llvm_jit_function_return_type() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
             llvm::LLVMCompiler::from_source(
                 r#"
-            let x 0;
-            // let y 0;
-            
-            while < x 1000
-                let i 0;
-                while < i 100
-                    := x + x 1;
-                    := i + i 1;
-                end
-            end
-            
-            return + x i;
-            "#,
+                 fn isodd (n) : bool
+                     return == % n 2 1
+                 end
+
+                 let x 0
+                 if isodd (3)
+                     := x 1
+                 end
+                 return x
+         "#,
                 &config
             )
             .log_expect(""),
-            1100.0
+            1.0
         );
     }
 
     #[test]
-    fn llvm_jit_if_else() {
+    fn llvm_jit_let_without_value() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
             llvm::LLVMCompiler::from_source(
                 r#"
-         let x 0;
-         if < x 1
-             return 1;
-         else
-             return 2;
-         end
+                 let x ; := x 5 ; return x
          "#,
                 &config
             )
             .log_expect(""),
-            1.0
+            5.0
         );
     }
 
     #[test]
-    fn llvm_jit_only_if() {
+    fn llvm_jit_global_from_function() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
             llvm::LLVMCompiler::from_source(
                 r#"
-                 let x 10;
-                 let y 2
-                 
-                 if < x y
-                     return y
+                 fn setcount (x)
+                     global count * x 2
+                     return 0
                  end
-                 
-                 return x
+                 let r setcount (21)
+                 return count
          "#,
                 &config
             )
             .log_expect(""),
-            10.0
+            42.0
         );
     }
 
     #[test]
-    fn llvm_jit_function_call() {
+    fn llvm_jit_top_level_variables() {
+        // functions see top-level variables like in the interpreter, even ones bound after them
+        let config = CompileConfig::from(true, false);
+        let source =
+            "fn bump ()\n:= count + count 1\nreturn count\nend\nlet count 5\nlet r bump ()\nreturn + count r";
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            12.0
+        );
+    }
+
+    #[test]
+    fn llvm_jit_function_redefinition() {
         let config = CompileConfig::from(true, true);
         assert_eq!(
             llvm::LLVMCompiler::from_source(
                 r#"
-                 fn sum (x y)
-                     return + x y;
+                 fn f (x)
+                     return + x 1
                  end
- 
-                 let i 10;
-                 let d 2;
- 
-                 let z sum (i d);
- 
-                 return z
+                 let a f (1)
+                 fn f (x)
+                     return * x 10
+                 end
+                 let b f (1)
+                 return + * a 100 b
          "#,
                 &config
             )
             .log_expect(""),
-            12.0
+            210.0
         );
     }
 
     #[test]
-    fn llvm_jit_collatz_conjecture() {
-        let config = CompileConfig::from(true, true);
+    fn llvm_jit_infix() {
+        let mut config = CompileConfig::from(true, true);
+        config.syntax = Syntax::Infix;
         assert_eq!(
             llvm::LLVMCompiler::from_source(
                 r#"
-                fn collatz (n)
-                while > n 1
-                    if == % n 2 0
-                        := n / n 2
-                    else
-                        := n + * 3 n 1
-                    end
-                    // cannot print in llvm jit
-                    // print n
-                end
-                return n
-            end
-            
-            return collatz (123)
+                 fn sum(x, y)
+                     return x + y
+                 end
+
+                 let z = sum(1 + 2 * 3, 5)
+                 return z
          "#,
                 &config
             )
             .log_expect(""),
-            1.0
+            12.0
+        );
+    }
+
+    #[test]
+    fn llvm_object_file_native_cpu() {
+        let mut config = CompileConfig::from(false, false);
+        config.cpu = String::from("native");
+        config.features = String::from("+sse2");
+        // the given features are added after the host's
+        let (_, features) = llvm::cpu_and_features(&config);
+        assert!(features.ends_with(",+sse2"), "{features}");
+
+        let context = inkwell::context::Context::create();
+        let builder = context.create_builder();
+        let module = context.create_module("main");
+        let fpm = inkwell::passes::PassManager::create(&module);
+        let nodes = parse_source("let x 2; return * x 3", Syntax::Rpn).log_expect("");
+        llvm::LLVMCompiler::new(&context, &builder, &module, &fpm)
+            .codegen(nodes)
+            .log_expect("");
+
+        let object = tempfile::Builder::new()
+            .suffix(".o")
+            .tempfile()
+            .log_expect("");
+        llvm::write_object_file(&module, &config, object.path()).log_expect("");
+        let len = std::fs::metadata(object.path()).log_expect("").len();
+        assert!(len > 0);
+    }
+
+    #[test]
+    fn llvm_jit_rand_seed() {
+        let config = CompileConfig::builder().jit(true).seed(42).build();
+        let source = "let a rand\nlet b randint 1 6\nreturn + * a 10 b";
+        let jit = llvm::LLVMCompiler::from_source(source, &config).log_expect("");
+        assert_eq!(
+            llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+            jit
         );
+        // both backends draw from the same generator
+        assert_eq!(Interpreter::from_source(source, &config), jit);
+    }
+
+    #[test]
+    fn llvm_fold_constant_calls() {
+        let context = inkwell::context::Context::create();
+        let builder = context.create_builder();
+        let module = context.create_module("main");
+        let fpm = inkwell::passes::PassManager::create(&module);
+        let nodes = parse_source(
+            "fn sum (x y)\nreturn + x y\nend\nreturn sum (2 3)",
+            Syntax::Rpn,
+        )
+        .log_expect("");
+        let main = llvm::LLVMCompiler::new(&context, &builder, &module, &fpm)
+            .codegen(nodes)
+            .log_expect("");
+
+        let ir = main.print_to_string().to_string();
+        assert!(!ir.contains("call"), "{ir}");
+        assert!(ir.contains("ret double 5.000000e+00"), "{ir}");
+    }
+
+    #[test]
+    fn llvm_jit_fold_matches_codegen() {
+        let config = CompileConfig::from(true, false);
+        for (source, expected) in [
+            // folding `f (3)` must not change the result, which `f (a)` computes at run time
+            (
+                "fn f (x)\nif > x 1\nreturn 7\nend\nreturn 8\nend\nlet a 3\nlet b f (3)\nlet c f (a)\n\
+                 return + * 10 b c",
+                77.0,
+            ),
+            // a call that never returns, in code that never runs, does not stop compilation
+            (
+                "fn spin (x)\nwhile 1\n:= x + x 1\nend\nreturn x\nend\nif 0\nlet s spin (1)\nend\nreturn 2",
+                2.0,
+            ),
+        ] {
+            assert_eq!(
+                llvm::LLVMCompiler::from_source(source, &config).log_expect(""),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn llvm_emit_bitcode() {
+        let name = std::env::temp_dir().join("laspa-emit-bitcode");
+        let config = CompileConfig::builder()
+            .name(name.to_string_lossy())
+            .emit_bitcode(true)
+            .build();
+
+        llvm::LLVMCompiler::from_source("let x 2; return * x 3", &config).log_expect("");
+        let path = name.with_extension("bc");
+        let bitcode = std::fs::read(&path).log_expect("");
+        std::fs::remove_file(&path).log_expect("");
+        assert!(bitcode.starts_with(b"BC\xC0\xDE"));
+    }
+
+    #[test]
+    fn llvm_separate_objects() {
+        let dir = std::env::temp_dir().join("laspa-separate-objects");
+        std::fs::create_dir_all(&dir).log_expect("");
+        let config = CompileConfig::builder()
+            .name(dir.join("main").to_string_lossy())
+            .link(false)
+            .separate_objects(true)
+            .build();
+        let source = "fn add (x y)\nreturn + x y\nend\nfn twice (x)\nreturn * x 2\nend\n\
+                      let n 2\nlet s add (n 1)\nreturn twice (s)";
+
+        let result = llvm::LLVMCompiler::from_source(source, &config);
+        let mut objects: Vec<_> = std::fs::read_dir(&dir)
+            .log_expect("")
+            .map(|entry| entry.log_expect("").file_name())
+            .collect();
+        objects.sort();
+        std::fs::remove_dir_all(&dir).log_expect("");
+
+        assert_eq!(result, Ok(0.0));
+        assert_eq!(objects, ["main-add.o", "main-twice.o", "main.o"]);
+    }
+
+    #[test]
+    fn llvm_link_failure_removes_object() {
+        let dir = std::env::temp_dir().join("laspa-link-failure");
+        std::fs::create_dir_all(&dir).log_expect("");
+        let mut config = CompileConfig::from(false, false);
+        config.name = dir.join("main").to_string_lossy().into_owned();
+        // linking fails because there is no clang to run (no other test here links, so none are affected)
+        std::env::set_var("LLVM_SYS_160_PREFIX", dir.join("no-llvm"));
+
+        let result = llvm::LLVMCompiler::from_source("return 1", &config);
+        let entries = std::fs::read_dir(&dir).log_expect("").count();
+        std::fs::remove_dir_all(&dir).log_expect("");
+
+        assert_eq!(result, Err("Failed to run clang"));
+        assert_eq!(entries, 0);
+    }
+
+    #[test]
+    fn llvm_optimize_hint() {
+        let context = inkwell::context::Context::create();
+        let builder = context.create_builder();
+        let module = context.create_module("main");
+        let fpm = inkwell::passes::PassManager::create(&module);
+        let nodes = parse_source(
+            r#"
+             fn cold (x)
+                 let y * x 2
+                 return + y 1
+             end
+             fn hot (x) #[optimize aggressive]
+                 let y * x 2
+                 return + y 1
+             end
+     "#,
+            Syntax::Rpn,
+        )
+        .log_expect("");
+        llvm::LLVMCompiler::new(&context, &builder, &module, &fpm)
+            .codegen(nodes)
+            .log_expect("");
+
+        let ir = |name| {
+            module
+                .get_function(name)
+                .log_expect("")
+                .print_to_string()
+                .to_string()
+        };
+        assert!(ir("cold").contains("alloca"));
+        assert!(!ir("hot").contains("alloca"));
     }
 
     #[test]
@@ -1,22 +1,29 @@
-use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    fs,
-    hash::{Hash, Hasher},
-    path::Path,
-    process::Command,
-};
+use std::{collections::HashMap, path::Path, process::Command};
 
-use crate::{Compile, CompileConfig, FnExpr, Node, Op};
+use crate::{
+    check_config, children, fold_constant_calls, parse_source, BindExpr, Builtin, Compile,
+    CompileConfig, FnExpr, Node, Op, OptimizeHint, Type, UnaryOp,
+};
 use inkwell::{
     self,
+    attributes::{Attribute, AttributeLoc},
     builder::Builder,
     context::Context,
-    module::Module,
+    execution_engine::ExecutionEngine,
+    module::{Linkage, Module},
     passes::PassManager,
-    targets::{CodeModel, InitializationConfig, RelocMode, Target},
-    types::BasicMetadataTypeEnum,
+    targets::{
+        CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple,
+    },
+    types::{BasicMetadataTypeEnum, FunctionType},
     values::{BasicMetadataValueEnum, FloatValue, FunctionValue, IntValue, PointerValue},
+    AddressSpace,
 };
+use laspa_std::runtime;
+
+/// The symbol of the generated function holding the top-level code. With [`CompileConfig::entry`], this
+/// function calls the entry function, so the JIT and executables always start here.
+const MAIN: &str = "main";
 
 #[macro_export]
 macro_rules! log_and_exit {
@@ -84,8 +91,18 @@ pub struct LLVMCompiler<'a, 'ctx> {
     pub builder: &'a Builder<'ctx>,
     pub module: &'a Module<'ctx>,
     pub fpm: &'a PassManager<FunctionValue<'ctx>>,
+    /// Runs after `fpm` on functions annotated with `#[optimize aggressive]`.
+    hot_fpm: PassManager<FunctionValue<'ctx>>,
     pub variables: Vec<HashMap<String, inkwell::values::PointerValue<'ctx>>>,
+    /// The latest definition of each function. Redefining a function adds a new LLVM function
+    /// (which LLVM renames), so calls are resolved through this map rather than by symbol name.
+    functions: HashMap<String, FunctionValue<'ctx>>,
+    /// The default of each parameter of a function, by the function's LLVM name. A call that leaves out
+    /// trailing arguments passes the defaults instead.
+    defaults: HashMap<String, Vec<Option<f64>>>,
     fn_value_opt: Option<FunctionValue<'ctx>>,
+    /// If set, `main` seeds the generator of `rand` and `randint` with it before running the program.
+    pub seed: Option<u64>,
 }
 
 impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
@@ -96,33 +113,53 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
         fpm: &'a PassManager<FunctionValue<'ctx>>,
     ) -> Self {
         let variables = vec![HashMap::new()];
+        let hot_fpm = PassManager::create(module);
+        optimize_hot_ir(&hot_fpm);
         Self {
             context,
             builder,
             module,
             fpm,
+            hot_fpm,
             variables,
+            functions: HashMap::new(),
+            defaults: HashMap::new(),
             fn_value_opt: None,
+            seed: None,
         }
     }
 
-    pub fn codegen(&mut self, nodes: Vec<Node>) -> Result<FunctionValue<'ctx>, &'static str> {
+    /// Generate `main` for a program, after folding its constant calls with [`fold_constant_calls`].
+    pub fn codegen(&mut self, mut nodes: Vec<Node>) -> Result<FunctionValue<'ctx>, &'static str> {
+        fold_constant_calls(&mut nodes);
         self.gen_main(nodes)
     }
 
     pub fn gen_main(&mut self, nodes: Vec<Node>) -> Result<FunctionValue<'ctx>, &'static str> {
         let main_type = self.context.f64_type().fn_type(&[], false);
-        let main_func = self.module.add_function("main", main_type, None);
+        let main_func = self.module.add_function(MAIN, main_type, None);
 
         let basic_block = self.context.append_basic_block(main_func, "entry");
         self.builder.position_at_end(basic_block);
 
         self.fn_value_opt = Some(main_func);
 
-        let ret = self
-            .gen_body(&nodes)?
-            .as_float()
-            .log_expect("Expected float value. Comparisons cannot be returned");
+        if let Some(seed) = self.seed {
+            let i64_type = self.context.i64_type();
+            let seed_fn = self.runtime_function(
+                "laspa_seed_rand",
+                self.context.void_type().fn_type(&[i64_type.into()], false),
+            );
+            self.builder.build_call(
+                seed_fn,
+                &[i64_type.const_int(seed, false).into()],
+                "seedcall",
+            );
+        }
+
+        // top-level variables are globals, so functions defined before their `let` can use them too
+        self.declare_globals(&nodes);
+        let ret = self.gen_body(&nodes)?;
 
         if self
             .builder
@@ -131,7 +168,7 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
             .get_terminator()
             .is_none()
         {
-            self.builder.build_return(Some(&ret));
+            self.build_return_value(&ret)?;
         }
 
         Ok(main_func)
@@ -140,29 +177,103 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
     pub fn gen_body(&mut self, nodes: &[Node]) -> Result<LLVMValue<'ctx>, &'static str> {
         let mut result: Option<LLVMValue<'ctx>> = None;
         for node in nodes {
-            result = Some(self.gen_expr(node)?);
+            let value = self.gen_expr(node)?;
 
-            if let Node::ReturnExpr(_) = node {
-                return Ok(result.unwrap());
+            // nothing may follow a `return` (e.g. `return 5 return 6` returns 5), as the block is terminated
+            if self.block_terminated() {
+                return Ok(value);
+            }
+            if node.has_value() {
+                result = Some(value);
             }
         }
+        // a body without a value (including an empty program) evaluates to 0
         Ok(result.unwrap_or(LLVMValue::Float(self.context.f64_type().const_float(0.0))))
     }
 
+    /// Whether the current block already ends in a terminator (e.g. a `return` in a loop body), so no
+    /// branch may be added after it.
+    fn block_terminated(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .and_then(|block| block.get_terminator())
+            .is_some()
+    }
+
+    /// Declare a module global for each variable bound with `let` at the top level, outside of functions.
+    fn declare_globals(&self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::FnExpr(_) => {}
+                Node::BindExpr(e) if !matches!(e.value[..], [Node::FnRef(_)]) => {
+                    self.global_ptr(&e.name);
+                    self.declare_globals(&e.value);
+                }
+                node => children(node)
+                    .into_iter()
+                    .for_each(|body| self.declare_globals(body)),
+            }
+        }
+    }
+
+    /// The module global holding a variable of the global scope, added (as `0`) if it does not exist yet.
+    fn global_ptr(&self, name: &str) -> PointerValue<'ctx> {
+        let f64_type = self.context.f64_type();
+        let global = self.module.get_global(name).unwrap_or_else(|| {
+            let global = self.module.add_global(f64_type, None, name);
+            global.set_initializer(&f64_type.const_float(0.0));
+            global
+        });
+        global.as_pointer_value()
+    }
+
+    /// Find a variable in the current function's scope, falling back to a module global.
+    fn variable_ptr(&self, name: &str) -> Option<PointerValue<'ctx>> {
+        self.variables
+            .last()
+            .log_expect("No variable scopes found")
+            .get(name)
+            .copied()
+            .or_else(|| self.module.get_global(name).map(|g| g.as_pointer_value()))
+    }
+
     pub fn gen_expr(&mut self, node: &Node) -> Result<LLVMValue<'ctx>, &'static str> {
         match node {
             Node::Number(n) => {
                 return Ok(self.context.f64_type().const_float(n.0).into());
             }
+            // a literal is an i1 like a comparison result
+            Node::BoolLit(b) => {
+                return Ok(LLVMValue::Int(
+                    self.context.bool_type().const_int(*b as u64, false),
+                ));
+            }
+            // logical operators combine conditions, so they take and produce an i1 like comparisons
+            Node::BinaryExpr(e) if e.op.is_logical() => {
+                let lhs = self.gen_body(&e.lhs)?;
+                let lhs = self.coerce_bool(lhs);
+                let rhs = self.gen_body(&e.rhs)?;
+                let rhs = self.coerce_bool(rhs);
+                let value = match e.op {
+                    Op::And => self.builder.build_and(lhs, rhs, "andtmp"),
+                    _ => self.builder.build_or(lhs, rhs, "ortmp"),
+                };
+                return Ok(LLVMValue::Int(value));
+            }
             Node::BinaryExpr(e) => {
-                let lhs = self
-                    .gen_body(&e.lhs)?
-                    .as_float()
-                    .log_expect("Expected float value. Comparisons cannot be used for operations");
-                let rhs = self
-                    .gen_body(&e.rhs)?
-                    .as_float()
-                    .log_expect("Expected float value. Comparisons cannot be used for operations");
+                let lhs = self.gen_body(&e.lhs)?;
+                let rhs = self.gen_body(&e.rhs)?;
+                // two conditions are compared as bools (e.g. `== > 3 1 < 1 3` is true)
+                if let (Op::Eqt, LLVMValue::Int(lhs), LLVMValue::Int(rhs)) = (e.op, &lhs, &rhs) {
+                    return Ok(LLVMValue::Int(self.builder.build_int_compare(
+                        inkwell::IntPredicate::EQ,
+                        *lhs,
+                        *rhs,
+                        "eqttmp",
+                    )));
+                }
+                let lhs = self.coerce_float(lhs);
+                let rhs = self.coerce_float(rhs);
 
                 match e.op {
                     Op::Add => {
@@ -190,6 +301,9 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                             self.builder.build_float_rem(lhs, rhs, "modtmp"),
                         ));
                     }
+                    Op::EuclidMod => {
+                        return Ok(LLVMValue::Float(self.gen_euclid_mod(lhs, rhs)));
+                    }
                     Op::Gt => {
                         return Ok(LLVMValue::Int(self.builder.build_float_compare(
                             inkwell::FloatPredicate::OGT,
@@ -214,59 +328,104 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                             "eqttmp",
                         )));
                     }
+                    Op::And | Op::Or => unreachable!("logical operators are generated above"),
                 }
             }
+            // binding a function reference adds the function under the new name
+            Node::BindExpr(e) if matches!(e.value[..], [Node::FnRef(_)]) => {
+                if let [Node::FnRef(target)] = &e.value[..] {
+                    let function = *self.functions.get(target).ok_or("Function not found")?;
+                    self.functions.insert(e.name.clone(), function);
+                }
+            }
+            Node::FnRef(_) => return Err("Function references cannot be used as numbers"),
             Node::BindExpr(e) => {
-                let value = self
-                    .gen_body(&e.value)?
-                    .as_float()
-                    .log_expect("Expected float value");
+                // a bool (e.g. `let b false`) is stored as 1 or 0
+                let value = self.gen_body(&e.value)?;
+                let value = self.coerce_float(value);
 
-                let f64_type = self.context.f64_type();
-                let alloca = self.builder.build_alloca(f64_type, e.name.as_str());
-                self.builder.build_store(alloca, value);
+                // a top-level `let` binds in the global scope like `global`, so functions can use it
+                let ptr = if self.variables.len() == 1 {
+                    self.global_ptr(&e.name)
+                } else {
+                    self.builder
+                        .build_alloca(self.context.f64_type(), e.name.as_str())
+                };
+                self.builder.build_store(ptr, value);
 
                 self.variables
                     .last_mut()
                     .log_expect("No variable scopes found")
-                    .insert(e.name.to_string(), alloca);
+                    .insert(e.name.to_string(), ptr);
+                // like the interpreter, a binding evaluates to the bound value
+                return Ok(LLVMValue::Float(value));
             }
-            Node::Variable(name) => {
+            Node::Variable(name) | Node::Reference(name) => {
                 let f64_type = self.context.f64_type();
-                let alloca = self
-                    .variables
-                    .last()
-                    .log_expect("No variable scopes found")
-                    .get(name)
-                    .unwrap_or_else(|| log_and_exit!("Variable '{}' not found!", name));
+                let ptr = match self.variable_ptr(name) {
+                    Some(ptr) => ptr,
+                    None => {
+                        log::error!("Variable '{}' not found!", name);
+                        return Err("Variable not found");
+                    }
+                };
 
-                let loaded_value = self.builder.build_load(f64_type, *alloca, name);
+                let loaded_value = self.builder.build_load(f64_type, ptr, name);
 
                 return Ok(LLVMValue::Float(loaded_value.into_float_value()));
             }
 
             Node::ReturnExpr(e) => {
-                let value = self
-                    .gen_body(&e.value)?
-                    .as_float()
-                    .log_expect("Expected float value. Comparisons cannot be used for operations");
+                let value = self.gen_body(&e.value)?;
 
-                self.builder.build_return(Some(&value));
-                return Ok(LLVMValue::Float(value));
+                self.build_return_value(&value)?;
+                return Ok(value);
             }
             Node::MutateExpr(e) => {
-                let value = self
-                    .gen_body(&e.value)?
-                    .as_float()
-                    .log_expect("Expected float value. Comparisons cannot be used for operations");
-                let alloca = self
-                    .variables
-                    .last()
-                    .log_expect("No variable scopes found")
-                    .get(&e.name)
-                    .unwrap_or_else(|| log_and_exit!("Variable '{}' not found to mutate!", e.name));
+                let value = self.gen_body(&e.value)?;
+                let value = self.coerce_float(value);
+                let ptr = match self.variable_ptr(&e.name) {
+                    Some(ptr) => ptr,
+                    None => {
+                        log::error!("Variable '{}' not found to mutate!", e.name);
+                        return Err("Variable not found");
+                    }
+                };
+
+                self.builder.build_store(ptr, value);
+                // the stored value, so `:= x := y 5` sets both
+                return Ok(LLVMValue::Float(value));
+            }
+            Node::GlobalExpr(e) => {
+                // a bool (e.g. `let b false`) is stored as 1 or 0
+                let value = self.gen_body(&e.value)?;
+                let value = self.coerce_float(value);
 
-                self.builder.build_store(*alloca, value);
+                self.builder.build_store(self.global_ptr(&e.name), value);
+                return Ok(LLVMValue::Float(value));
+            }
+            Node::UnaryExpr(e) => {
+                let value = self.gen_body(&e.operand)?;
+                match e.op {
+                    UnaryOp::Neg => {
+                        let value = self.coerce_float(value);
+                        return Ok(LLVMValue::Float(
+                            self.builder.build_float_neg(value, "negtmp"),
+                        ));
+                    }
+                    UnaryOp::Not => {
+                        let value = match value {
+                            LLVMValue::Int(value) => self.builder.build_not(value, "nottmp"),
+                            LLVMValue::Float(value) => self.builder.build_float_compare(
+                                inkwell::FloatPredicate::OEQ,
+                                value,
+                                self.context.f64_type().const_zero(),
+                                "nottmp",
+                            ),
+                        };
+                        return Ok(LLVMValue::Int(value));
+                    }
+                }
             }
             Node::WhileExpr(e) => {
                 let function = self
@@ -292,16 +451,91 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                 self.builder
                     .build_conditional_branch(cond, loop_body_bb, loop_end_bb);
 
-                // Generate the loop body
+                // Generate the loop body. A `return` in it already ends the block
                 self.builder.position_at_end(loop_body_bb);
-                for node in e.body.iter() {
-                    self.gen_expr(node)?;
+                self.gen_body(&e.body)?;
+                if !self.block_terminated() {
+                    self.builder.build_unconditional_branch(loop_cond_bb);
                 }
-                self.builder.build_unconditional_branch(loop_cond_bb);
 
                 // Position builder at the end block after the loop
                 self.builder.position_at_end(loop_end_bb);
             }
+            Node::ForExpr(e) => {
+                let f64_type = self.context.f64_type();
+                let start = self.gen_body(&e.start)?;
+                let start = self.coerce_float(start);
+                let end = self.gen_body(&e.end)?;
+                let end = self.coerce_float(end);
+                let step = self.gen_body(&e.step)?;
+                let step = self.coerce_float(step);
+
+                let alloca = self.builder.build_alloca(f64_type, e.var.as_str());
+                self.builder.build_store(alloca, start);
+                self.variables
+                    .last_mut()
+                    .log_expect("No variable scopes found")
+                    .insert(e.var.to_string(), alloca);
+
+                let function = self
+                    .builder
+                    .get_insert_block()
+                    .unwrap()
+                    .get_parent()
+                    .unwrap();
+
+                let loop_cond_bb = self.context.append_basic_block(function, "for_cond");
+                let loop_body_bb = self.context.append_basic_block(function, "for_body");
+                let loop_end_bb = self.context.append_basic_block(function, "for_end");
+
+                self.builder.build_unconditional_branch(loop_cond_bb);
+
+                // Count up while below the bound for a positive step, and down while above it for a negative one
+                self.builder.position_at_end(loop_cond_bb);
+                let i = self
+                    .builder
+                    .build_load(f64_type, alloca, e.var.as_str())
+                    .into_float_value();
+                let zero = f64_type.const_float(0.0);
+                let step_up = self.builder.build_float_compare(
+                    inkwell::FloatPredicate::OGT,
+                    step,
+                    zero,
+                    "stepup",
+                );
+                let step_down = self.builder.build_float_compare(
+                    inkwell::FloatPredicate::OLT,
+                    step,
+                    zero,
+                    "stepdown",
+                );
+                let below =
+                    self.builder
+                        .build_float_compare(inkwell::FloatPredicate::OLT, i, end, "below");
+                let above =
+                    self.builder
+                        .build_float_compare(inkwell::FloatPredicate::OGT, i, end, "above");
+                let up = self.builder.build_and(step_up, below, "countup");
+                let down = self.builder.build_and(step_down, above, "countdown");
+                let cond = self.builder.build_or(up, down, "forcond");
+                self.builder
+                    .build_conditional_branch(cond, loop_body_bb, loop_end_bb);
+
+                // Generate the loop body, then advance the loop variable unless the body returned
+                self.builder.position_at_end(loop_body_bb);
+                self.gen_body(&e.body)?;
+                if !self.block_terminated() {
+                    let i = self
+                        .builder
+                        .build_load(f64_type, alloca, e.var.as_str())
+                        .into_float_value();
+                    let next = self.builder.build_float_add(i, step, "nextvar");
+                    self.builder.build_store(alloca, next);
+                    self.builder.build_unconditional_branch(loop_cond_bb);
+                }
+
+                self.builder.position_at_end(loop_end_bb);
+            }
             Node::IfExpr(e) => {
                 let function = self
                     .builder
@@ -343,18 +577,18 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
 
                 // Generate then block
                 self.builder.position_at_end(then_bb);
-                for node in e.body.iter() {
-                    self.gen_expr(node)?;
+                self.gen_body(&e.body)?;
+                if !self.block_terminated() {
+                    self.builder.build_unconditional_branch(end_if_bb);
                 }
-                self.builder.build_unconditional_branch(end_if_bb);
 
                 // Generate else block if it exists
                 if let Some(else_bb) = else_bb {
                     self.builder.position_at_end(else_bb);
-                    for node in e.else_body.iter() {
-                        self.gen_expr(node)?;
+                    self.gen_body(&e.else_body)?;
+                    if !self.block_terminated() {
+                        self.builder.build_unconditional_branch(end_if_bb);
                     }
-                    self.builder.build_unconditional_branch(end_if_bb);
                 }
 
                 // Position builder at the end block after the if statement
@@ -365,6 +599,20 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                 let current_block = self.builder.get_insert_block().unwrap();
 
                 let function = self.compile_prototype(e)?;
+                let previous = self.functions.insert(e.name.clone(), function);
+                let defaults = e
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        Node::BindExpr(param) => match param.value.as_slice() {
+                            [Node::Number(n)] => Some(n.0),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect();
+                self.defaults
+                    .insert(function.get_name().to_string_lossy().into_owned(), defaults);
 
                 // got external function, returning only compiled prototype
                 // if self.function.body.is_none() {
@@ -384,19 +632,21 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                 // all paramters will be mutable by default
                 // so we need to create alloca for each of them
                 for (i, arg) in function.get_param_iter().enumerate() {
-                    let arg_name = if let Node::Variable(name) = &e.args[i] {
-                        name
-                    } else {
-                        log_and_exit!("Expected variable name")
+                    let (arg_name, ptr) = match &e.args[i] {
+                        Node::Variable(name) | Node::BindExpr(BindExpr { name, .. }) => {
+                            let alloca = self.create_entry_block_alloca(name);
+                            self.builder.build_store(alloca, arg);
+                            (name, alloca)
+                        }
+                        // reference parameters already point at the caller's variable
+                        Node::Reference(name) => (name, arg.into_pointer_value()),
+                        _ => log_and_exit!("Expected variable name"),
                     };
-                    let alloca = self.create_entry_block_alloca(arg_name);
-
-                    self.builder.build_store(alloca, arg);
 
                     self.variables
                         .last_mut()
                         .log_expect("No variable scopes found")
-                        .insert(arg_name.to_string(), alloca);
+                        .insert(arg_name.to_string(), ptr);
                 }
 
                 // compile body
@@ -407,34 +657,70 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
 
                 // return the whole thing after verification and optimization
                 if function.verify(true) {
-                    self.fpm.run_on(&function);
+                    match e.optimize {
+                        Some(OptimizeHint::None) => {
+                            for name in ["noinline", "optnone"] {
+                                let kind = Attribute::get_named_enum_kind_id(name);
+                                let attribute = self.context.create_enum_attribute(kind, 0);
+                                function.add_attribute(AttributeLoc::Function, attribute);
+                            }
+                        }
+                        Some(OptimizeHint::Aggressive) => {
+                            self.fpm.run_on(&function);
+                            self.hot_fpm.run_on(&function);
+                        }
+                        None => {
+                            self.fpm.run_on(&function);
+                        }
+                    }
 
                     // return Ok(function)
                 } else {
                     unsafe {
                         function.delete();
                     }
+                    match previous {
+                        Some(previous) => self.functions.insert(e.name.clone(), previous),
+                        None => self.functions.remove(&e.name),
+                    };
 
                     return Err("Invalid generated function.");
                 }
             }
             Node::FnCallExpr(e) => {
-                let mut compiled_args = Vec::with_capacity(e.args.len());
-
-                for arg in &e.args {
-                    compiled_args.push(self.gen_expr(arg)?.as_float().unwrap());
+                let function = *self.functions.get(&e.name).log_expect("Function not found");
+                let param_types = function.get_type().get_param_types();
+                let params = param_types.len();
+
+                let mut argsv: Vec<BasicMetadataValueEnum> = Vec::with_capacity(params);
+
+                for (arg, param_type) in e.args.iter().zip(param_types) {
+                    // reference parameters take a pointer to the caller's variable
+                    if param_type.is_pointer_type() {
+                        let ptr = match arg {
+                            Node::Variable(name) | Node::Reference(name) => self.variable_ptr(name),
+                            _ => None,
+                        };
+                        argsv.push(ptr.ok_or("Reference argument must be a variable")?.into());
+                    } else {
+                        // like in the interpreter, a comparison is passed as `1` or `0`
+                        let value = self.gen_body(std::slice::from_ref(arg))?;
+                        argsv.push(self.coerce_float(value).into());
+                    }
                 }
 
-                let argsv: Vec<BasicMetadataValueEnum> = compiled_args
-                    .iter()
-                    .by_ref()
-                    .map(|&val| val.into())
-                    .collect();
-
-                let function = self
-                    .module
-                    .get_function(&e.name)
-                    .log_expect("Function not found");
+                // arguments that are left out take the parameter's default
+                if argsv.len() < params {
+                    let name = function.get_name().to_string_lossy();
+                    let defaults = self
+                        .defaults
+                        .get(name.as_ref())
+                        .ok_or("Function not found")?;
+                    for default in &defaults[argsv.len()..] {
+                        let value = default.ok_or("Missing argument")?;
+                        argsv.push(self.context.f64_type().const_float(value).into());
+                    }
+                }
 
                 match self
                     .builder
@@ -442,34 +728,323 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
                     .try_as_basic_value()
                     .left()
                 {
+                    Some(value) if value.is_int_value() => {
+                        return Ok(LLVMValue::Int(value.into_int_value()))
+                    }
                     Some(value) => return Ok(LLVMValue::Float(value.into_float_value())),
                     None => return Err("Invalid call produced."),
                 };
             }
             Node::PrintStdoutExpr(e) => {
-                let value = self
-                    .gen_body(&e.value)?
-                    .as_float()
-                    .log_expect("Expected float value for print");
-                let print_fn = self.module.get_function("print_f64").unwrap_or_else(|| {
-                    let fn_type = self
-                        .context
-                        .f64_type()
-                        .fn_type(&[self.context.f64_type().into()], false);
-                    self.module.add_function("print_f64", fn_type, None)
-                });
+                let value = self.gen_body(&e.value)?;
+                let value = self.coerce_float(value);
+                let fn_type = self
+                    .context
+                    .void_type()
+                    .fn_type(&[self.context.f64_type().into()], false);
+                let print_fn = self.runtime_function("laspa_print_f64", fn_type);
                 self.builder
                     .build_call(print_fn, &[value.into()], "printcall");
             }
+            Node::BuiltinExpr(e) => {
+                let mut args = Vec::with_capacity(e.args.len());
+                for arg in &e.args {
+                    let value = self.gen_body(arg)?;
+                    args.push(self.coerce_float(value));
+                }
+                return self.gen_builtin(e.builtin, &args);
+            }
+            Node::Block(body) => {
+                return self.gen_body(body);
+            }
         }
         Ok(LLVMValue::Float(self.context.f64_type().const_float(0.0)))
     }
 
+    fn gen_builtin(
+        &mut self,
+        builtin: Builtin,
+        args: &[FloatValue<'ctx>],
+    ) -> Result<LLVMValue<'ctx>, &'static str> {
+        match builtin {
+            Builtin::IPow => Ok(LLVMValue::Float(self.gen_ipow(args[0], args[1]))),
+            Builtin::Sqrt => {
+                let f64_type = self.context.f64_type();
+                let sqrt_fn = self
+                    .runtime_function("laspa_sqrt", f64_type.fn_type(&[f64_type.into()], false));
+                self.builder
+                    .build_call(sqrt_fn, &[args[0].into()], "sqrtcall")
+                    .try_as_basic_value()
+                    .left()
+                    .map(|value| LLVMValue::Float(value.into_float_value()))
+                    .ok_or("Invalid call produced.")
+            }
+            Builtin::Sign => Ok(LLVMValue::Float(self.gen_sign(args[0]))),
+            Builtin::Round => self.gen_intrinsic("llvm.round.f64", args[0]),
+            Builtin::Trunc => self.gen_intrinsic("llvm.trunc.f64", args[0]),
+            Builtin::Rand | Builtin::RandInt | Builtin::Clock | Builtin::Hypot | Builtin::Dist => {
+                let f64_type = self.context.f64_type();
+                let name = match builtin {
+                    Builtin::Rand => "laspa_rand",
+                    Builtin::RandInt => "laspa_randint",
+                    Builtin::Hypot => "laspa_hypot",
+                    Builtin::Dist => "laspa_dist",
+                    _ => "laspa_clock",
+                };
+                let params = args
+                    .iter()
+                    .map(|_| f64_type.into())
+                    .collect::<Vec<BasicMetadataTypeEnum>>();
+                let runtime_fn = self.runtime_function(name, f64_type.fn_type(&params, false));
+                let args = args
+                    .iter()
+                    .map(|&arg| arg.into())
+                    .collect::<Vec<BasicMetadataValueEnum>>();
+                self.builder
+                    .build_call(runtime_fn, &args, "runtimecall")
+                    .try_as_basic_value()
+                    .left()
+                    .map(|value| LLVMValue::Float(value.into_float_value()))
+                    .ok_or("Invalid call produced.")
+            }
+        }
+    }
+
+    /// Call a unary `f64` LLVM intrinsic (e.g. `llvm.round.f64`).
+    fn gen_intrinsic(
+        &self,
+        name: &str,
+        arg: FloatValue<'ctx>,
+    ) -> Result<LLVMValue<'ctx>, &'static str> {
+        let f64_type = self.context.f64_type();
+        let function = self.runtime_function(name, f64_type.fn_type(&[f64_type.into()], false));
+        self.builder
+            .build_call(function, &[arg.into()], "intrinsiccall")
+            .try_as_basic_value()
+            .left()
+            .map(|value| LLVMValue::Float(value.into_float_value()))
+            .ok_or("Invalid call produced.")
+    }
+
+    /// The Euclidean modulo like [`f64::rem_euclid`]: the remainder, plus `|rhs|` if it is negative.
+    fn gen_euclid_mod(&self, lhs: FloatValue<'ctx>, rhs: FloatValue<'ctx>) -> FloatValue<'ctx> {
+        let zero = self.context.f64_type().const_zero();
+        let rem = self.builder.build_float_rem(lhs, rhs, "modrem");
+        let rhs_negative =
+            self.builder
+                .build_float_compare(inkwell::FloatPredicate::OLT, rhs, zero, "modrhsneg");
+        let negated = self.builder.build_float_neg(rhs, "modrhsnegated");
+        let abs = self
+            .builder
+            .build_select(rhs_negative, negated, rhs, "modrhsabs")
+            .into_float_value();
+        let adjusted = self.builder.build_float_add(rem, abs, "modadjusted");
+        let rem_negative =
+            self.builder
+                .build_float_compare(inkwell::FloatPredicate::OLT, rem, zero, "modremneg");
+        self.builder
+            .build_select(rem_negative, adjusted, rem, "mod")
+            .into_float_value()
+    }
+
+    /// `1` for positive numbers, `-1` for negative numbers, `0` for both zeros, and NaN for NaN.
+    fn gen_sign(&self, x: FloatValue<'ctx>) -> FloatValue<'ctx> {
+        let f64_type = self.context.f64_type();
+        let zero = f64_type.const_zero();
+        let positive =
+            self.builder
+                .build_float_compare(inkwell::FloatPredicate::OGT, x, zero, "signpos");
+        let negative =
+            self.builder
+                .build_float_compare(inkwell::FloatPredicate::OLT, x, zero, "signneg");
+        let nan = self
+            .builder
+            .build_float_compare(inkwell::FloatPredicate::UNO, x, x, "signnan");
+
+        let value = self.builder.build_select(nan, x, zero, "signzero");
+        let value = self.builder.build_select(
+            negative,
+            f64_type.const_float(-1.0),
+            value.into_float_value(),
+            "signneg",
+        );
+        self.builder
+            .build_select(
+                positive,
+                f64_type.const_float(1.0),
+                value.into_float_value(),
+                "sign",
+            )
+            .into_float_value()
+    }
+
+    /// Declare a function from the runtime library (`laspa-std`) or an LLVM intrinsic, or get it if it is
+    /// already declared.
+    fn runtime_function(&self, name: &str, fn_type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, fn_type, None))
+    }
+
+    /// Raise `base` to the power of `exp` (truncated to an integer) with a repeated squaring loop.
+    fn gen_ipow(&self, base: FloatValue<'ctx>, exp: FloatValue<'ctx>) -> FloatValue<'ctx> {
+        let f64_type = self.context.f64_type();
+        let i64_type = self.context.i64_type();
+        let function = self
+            .builder
+            .get_insert_block()
+            .unwrap()
+            .get_parent()
+            .unwrap();
+
+        let n = self
+            .builder
+            .build_float_to_signed_int(exp, i64_type, "ipowexp");
+        let negative = self.builder.build_int_compare(
+            inkwell::IntPredicate::SLT,
+            n,
+            i64_type.const_zero(),
+            "ipowneg",
+        );
+        let negated = self.builder.build_int_neg(n, "ipownegexp");
+        let n = self
+            .builder
+            .build_select(negative, negated, n, "ipowabs")
+            .into_int_value();
+
+        let entry_bb = self.builder.get_insert_block().unwrap();
+        let loop_cond_bb = self.context.append_basic_block(function, "ipow_cond");
+        let loop_body_bb = self.context.append_basic_block(function, "ipow_body");
+        let loop_end_bb = self.context.append_basic_block(function, "ipow_end");
+        self.builder.build_unconditional_branch(loop_cond_bb);
+
+        // result, base and the remaining exponent are carried around the loop
+        self.builder.position_at_end(loop_cond_bb);
+        let result = self.builder.build_phi(f64_type, "ipowresult");
+        let square = self.builder.build_phi(f64_type, "ipowbase");
+        let remaining = self.builder.build_phi(i64_type, "ipown");
+        let cond = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            remaining.as_basic_value().into_int_value(),
+            i64_type.const_zero(),
+            "ipowcond",
+        );
+        self.builder
+            .build_conditional_branch(cond, loop_body_bb, loop_end_bb);
+
+        // multiply the result by the base when the low bit is set, then square the base
+        self.builder.position_at_end(loop_body_bb);
+        let result_value = result.as_basic_value().into_float_value();
+        let square_value = square.as_basic_value().into_float_value();
+        let remaining_value = remaining.as_basic_value().into_int_value();
+        let bit = self
+            .builder
+            .build_and(remaining_value, i64_type.const_int(1, false), "ipowbit");
+        let odd = self.builder.build_int_compare(
+            inkwell::IntPredicate::NE,
+            bit,
+            i64_type.const_zero(),
+            "ipowodd",
+        );
+        let multiplied = self
+            .builder
+            .build_float_mul(result_value, square_value, "ipowmul");
+        let next_result = self
+            .builder
+            .build_select(odd, multiplied, result_value, "ipownext")
+            .into_float_value();
+        let next_square = self
+            .builder
+            .build_float_mul(square_value, square_value, "ipowsquare");
+        let next_remaining = self.builder.build_right_shift(
+            remaining_value,
+            i64_type.const_int(1, false),
+            false,
+            "ipowshift",
+        );
+        self.builder.build_unconditional_branch(loop_cond_bb);
+
+        result.add_incoming(&[
+            (&f64_type.const_float(1.0), entry_bb),
+            (&next_result, loop_body_bb),
+        ]);
+        square.add_incoming(&[(&base, entry_bb), (&next_square, loop_body_bb)]);
+        remaining.add_incoming(&[(&n, entry_bb), (&next_remaining, loop_body_bb)]);
+
+        // a negative exponent gives the reciprocal
+        self.builder.position_at_end(loop_end_bb);
+        let result_value = result.as_basic_value().into_float_value();
+        let reciprocal =
+            self.builder
+                .build_float_div(f64_type.const_float(1.0), result_value, "ipowrecip");
+        self.builder
+            .build_select(negative, reciprocal, result_value, "ipow")
+            .into_float_value()
+    }
+
+    /// Return a value from the current function. Comparisons are converted to `0.0`/`1.0` unless the
+    /// function is annotated to return a bool.
+    fn build_return_value(&self, value: &LLVMValue<'ctx>) -> Result<(), &'static str> {
+        let function = self
+            .builder
+            .get_insert_block()
+            .unwrap()
+            .get_parent()
+            .unwrap();
+        let returns_bool = function
+            .get_type()
+            .get_return_type()
+            .map_or(false, |ty| ty.is_int_type());
+
+        match value {
+            LLVMValue::Int(val) if returns_bool => {
+                self.builder.build_return(Some(val));
+            }
+            LLVMValue::Int(val) => {
+                let val = self.builder.build_unsigned_int_to_float(
+                    *val,
+                    self.context.f64_type(),
+                    "booltmp",
+                );
+                self.builder.build_return(Some(&val));
+            }
+            LLVMValue::Float(_) if returns_bool => return Err("Expected bool return value"),
+            LLVMValue::Float(val) => {
+                self.builder.build_return(Some(val));
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     fn fn_value(&self) -> FunctionValue<'ctx> {
         self.fn_value_opt.unwrap()
     }
 
+    /// Use a comparison result as a number (`1` or `0`), like [`crate::bool_to_num`] in the interpreter.
+    /// An i1 condition from a value: comparisons are used as they are, and a number is true if it is not `0`.
+    fn coerce_bool(&self, value: LLVMValue<'ctx>) -> IntValue<'ctx> {
+        match value {
+            LLVMValue::Int(value) => value,
+            LLVMValue::Float(value) => self.builder.build_float_compare(
+                inkwell::FloatPredicate::UNE,
+                value,
+                self.context.f64_type().const_zero(),
+                "booltmp",
+            ),
+        }
+    }
+
+    fn coerce_float(&self, value: LLVMValue<'ctx>) -> FloatValue<'ctx> {
+        match value {
+            LLVMValue::Float(value) => value,
+            LLVMValue::Int(value) => {
+                self.builder
+                    .build_unsigned_int_to_float(value, self.context.f64_type(), "booltmp")
+            }
+        }
+    }
+
     fn create_entry_block_alloca(&self, name: &str) -> PointerValue<'ctx> {
         let builder = self.context.create_builder();
 
@@ -485,21 +1060,28 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
 
     fn compile_prototype(&mut self, proto: &FnExpr) -> Result<FunctionValue<'ctx>, &'static str> {
         let ret_type = self.context.f64_type();
-        let args_types = std::iter::repeat(ret_type)
-            .take(proto.args.len())
-            .map(|f| f.into())
+        let args_types = proto
+            .args
+            .iter()
+            .map(|arg| match arg {
+                Node::Reference(_) => ret_type.ptr_type(AddressSpace::default()).into(),
+                _ => ret_type.into(),
+            })
             .collect::<Vec<BasicMetadataTypeEnum>>();
         let args_types = args_types.as_slice();
 
-        let fn_type = self.context.f64_type().fn_type(args_types, false);
+        let fn_type = match proto.return_type {
+            Some(Type::Bool) => self.context.bool_type().fn_type(args_types, false),
+            _ => self.context.f64_type().fn_type(args_types, false),
+        };
         let fn_val = self.module.add_function(proto.name.as_str(), fn_type, None);
 
         // set arguments names
         for (i, arg) in fn_val.get_param_iter().enumerate() {
-            let name = if let Node::Variable(name) = &proto.args[i] {
-                name
-            } else {
-                log_and_exit!("Expected variable name")
+            let name = match &proto.args[i] {
+                Node::Variable(name) | Node::Reference(name) => name,
+                Node::BindExpr(BindExpr { name, .. }) => name,
+                _ => log_and_exit!("Expected variable name"),
             };
             arg.set_name(name);
         }
@@ -509,6 +1091,158 @@ impl<'a, 'ctx> LLVMCompiler<'a, 'ctx> {
     }
 }
 
+/// Write a module to an object file for the target in `config`. The host triple is used unless a target is set,
+/// and a CPU of `native` uses the host CPU and its features.
+pub(crate) fn write_object_file(
+    module: &Module,
+    config: &CompileConfig,
+    path: &Path,
+) -> Result<(), &'static str> {
+    let machine = target_machine(config).map_err(|e| {
+        log::error!("{}", e);
+        "Error creating target machine"
+    })?;
+    machine
+        .write_to_file(module, FileType::Object, path)
+        .map_err(|e| {
+            log::error!("Error writing object file: {}", e);
+            "Error writing object file"
+        })
+}
+
+/// Describe the LLVM backend for bug reports: the version of the linked LLVM, the host target, and whether
+/// the JIT is available on it.
+pub fn backend_info() -> String {
+    let (major, minor, patch) = inkwell::support::get_llvm_version();
+    let jit = Target::initialize_native(&InitializationConfig::default()).is_ok() && {
+        let context = Context::create();
+        let module = context.create_module("jit_check");
+        module
+            .create_jit_execution_engine(inkwell::OptimizationLevel::None)
+            .is_ok()
+    };
+    format!(
+        "LLVM {major}.{minor}.{patch}\ntarget: {}\nJIT: {}",
+        TargetMachine::get_default_triple()
+            .as_str()
+            .to_string_lossy(),
+        if jit { "available" } else { "unavailable" }
+    )
+}
+
+/// Compile source to an object file in memory, without writing any files or linking. The object declares the
+/// runtime functions, so it must be linked with the runtime library (`laspa-std`) like a compiled file.
+pub fn compile_to_object(source: &str, config: &CompileConfig) -> Result<Vec<u8>, String> {
+    let nodes = parse_checked(source, config)?;
+
+    let context = Context::create();
+    let builder = context.create_builder();
+    let module = context.create_module("main");
+    let fpm = PassManager::create(&module);
+    optimize_ir(&fpm, inkwell::OptimizationLevel::Aggressive);
+    let mut compiler = LLVMCompiler::new(&context, &builder, &module, &fpm);
+    compiler.seed = config.seed;
+    compiler.codegen(nodes)?;
+    module.verify().map_err(|e| e.to_string())?;
+
+    Target::initialize_native(&InitializationConfig::default())?;
+    let buffer = target_machine(config)?
+        .write_to_memory_buffer(&module, FileType::Object)
+        .map_err(|e| e.to_string())?;
+    Ok(buffer.as_slice().to_vec())
+}
+
+/// Parse source and apply [`check_config`].
+fn parse_checked(source: &str, config: &CompileConfig) -> Result<Vec<Node>, String> {
+    let nodes = parse_source(source, config.syntax).map_err(|e| e.to_string())?;
+    check_config(nodes, config).map_err(|e| e.to_string())
+}
+
+/// A JIT for running many programs, e.g. in a long-running embedder. The session keeps one LLVM context, and
+/// each program gets its own module and execution engine, which are freed when [`JitSession::run`] returns.
+pub struct JitSession {
+    context: Context,
+    config: CompileConfig,
+}
+
+impl JitSession {
+    /// Create a session that parses and checks programs as `config` asks.
+    pub fn new(config: CompileConfig) -> Self {
+        Self {
+            context: Context::create(),
+            config,
+        }
+    }
+
+    /// JIT-compile and run a program, returning its result.
+    pub fn run(&self, source: &str) -> Result<f64, String> {
+        let nodes = parse_checked(source, &self.config)?;
+
+        let builder = self.context.create_builder();
+        let module = self.context.create_module("main");
+        let fpm = PassManager::create(&module);
+        optimize_ir(&fpm, inkwell::OptimizationLevel::Aggressive);
+        let mut compiler = LLVMCompiler::new(&self.context, &builder, &module, &fpm);
+        compiler.seed = self.config.seed;
+        compiler.codegen(nodes)?;
+
+        Target::initialize_native(&InitializationConfig::default())?;
+        // the engine takes ownership of the module, and both are disposed when it is dropped
+        let execution_engine = module
+            .create_jit_execution_engine(inkwell::OptimizationLevel::Aggressive)
+            .map_err(|e| e.to_string())?;
+        map_runtime(&module, &execution_engine);
+        let main_func = unsafe {
+            execution_engine
+                .get_function::<unsafe extern "C" fn() -> f64>(MAIN)
+                .map_err(|e| e.to_string())?
+        };
+        let result = unsafe { main_func.call() };
+        Ok(result)
+    }
+}
+
+/// The CPU and features to compile for. With a CPU of `native`, the given features come after the host's, so
+/// they can still turn some on or off (e.g. `-avx512f`).
+pub(crate) fn cpu_and_features(config: &CompileConfig) -> (String, String) {
+    if config.cpu != "native" {
+        return (config.cpu.clone(), config.features.clone());
+    }
+    let mut features = TargetMachine::get_host_cpu_features().to_string();
+    if !config.features.is_empty() {
+        features.push(',');
+        features.push_str(&config.features);
+    }
+    (TargetMachine::get_host_cpu_name().to_string(), features)
+}
+
+/// Create a target machine for the target, CPU and features in `config`.
+fn target_machine(config: &CompileConfig) -> Result<TargetMachine, String> {
+    let target_triple = match &config.target {
+        Some(triple) => {
+            Target::initialize_all(&InitializationConfig::default());
+            TargetTriple::create(triple)
+        }
+        None => TargetMachine::get_default_triple(),
+    };
+    let (cpu, features) = cpu_and_features(config);
+
+    let target = Target::from_triple(&target_triple).map_err(|e| e.to_string())?;
+    target
+        .create_target_machine(
+            &target_triple,
+            &cpu,
+            &features,
+            inkwell::OptimizationLevel::Aggressive,
+            RelocMode::Default,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| {
+            let triple = target_triple.as_str().to_string_lossy();
+            format!("Cannot create a target machine for {triple}")
+        })
+}
+
 impl Compile for LLVMCompiler<'_, '_> {
     type Output = Result<f64, &'static str>;
 
@@ -523,11 +1257,13 @@ impl Compile for LLVMCompiler<'_, '_> {
         // Optimization passes
         optimize_ir(&fpm, inkwell::OptimizationLevel::Aggressive);
 
+        let nodes = check_config(nodes, config).log_expect("Error parsing source");
         let mut compiler = LLVMCompiler::new(&context, &builder, &module, &fpm);
+        compiler.seed = config.seed;
 
         config.progress.set_message("Compiling AST");
         config.progress.inc(1);
-        compiler.codegen(nodes).log_expect("Failed to generate IR");
+        compiler.codegen(nodes)?;
 
         if config.show_ir {
             let ir = module.print_to_string();
@@ -544,10 +1280,11 @@ impl Compile for LLVMCompiler<'_, '_> {
             let execution_engine = module
                 .create_jit_execution_engine(inkwell::OptimizationLevel::Aggressive)
                 .log_expect("Failed to create JIT execution engine");
+            map_runtime(&module, &execution_engine);
 
             let main_func = unsafe {
                 execution_engine
-                    .get_function::<unsafe extern "C" fn() -> f64>("main")
+                    .get_function::<unsafe extern "C" fn() -> f64>(MAIN)
                     .log_expect("Failed to get main function")
             };
             let result = unsafe { main_func.call() };
@@ -561,41 +1298,104 @@ impl Compile for LLVMCompiler<'_, '_> {
         config.progress.inc(1);
         module.verify().log_expect("Error verifying module");
 
-        let hash = compute_hash(&module.to_string());
-        let tempname = format!("output-{hash}.o");
-        let temp_path = Path::new(&tempname);
+        if config.emit_bitcode {
+            config.progress.set_message("Writing bitcode");
+            config.progress.inc(1);
+            let bitcode_name = format!("{}.bc", config.name);
+            if !module.write_bitcode_to_path(Path::new(&bitcode_name)) {
+                return Err("Error writing bitcode");
+            }
+            return Ok(0.0);
+        }
+
+        // each part is written to its own object file, named after its function (`None` for `main`)
+        let split = if config.separate_objects {
+            split_module(&module)
+        } else {
+            Vec::new()
+        };
+        let parts: Vec<(Option<&str>, &Module)> = if config.separate_objects {
+            split
+                .iter()
+                .map(|(function, part)| (function.as_deref(), part))
+                .collect()
+        } else {
+            vec![(None, &module)]
+        };
+
+        if !config.link {
+            config.progress.set_message("Writing object file");
+            config.progress.inc(1);
+            for (function, part) in &parts {
+                let object_name = match function {
+                    Some(function) => format!("{}-{}.o", config.name, function),
+                    None => format!("{}.o", config.name),
+                };
+                write_object_file(part, config, Path::new(&object_name))?;
+            }
+            return Ok(0.0);
+        }
+
+        // only linking needs clang, so the JIT and object files work without the prefix
+        let clang_path = std::env::var("LLVM_SYS_160_PREFIX").map_err(|_| {
+            "LLVM_SYS_160_PREFIX is not set. Linking needs it to point to an LLVM 16 install with clang \
+             (e.g. `export LLVM_SYS_160_PREFIX=/usr/local/opt/llvm@16`)"
+        })?;
+        let clang_path = clang_path + "/bin/clang";
+
+        // each object file gets a unique name next to the executable, so concurrent compiles don't clobber
+        // each other, and unless it is kept, it is deleted when it is dropped, including when linking fails
+        let output_dir = Path::new(&config.name)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
 
         config.progress.set_message("Writing object file");
         config.progress.inc(1);
-        let target_triple = inkwell::targets::TargetMachine::get_default_triple();
-        let target = inkwell::targets::Target::from_triple(&target_triple)
-            .log_expect("Error getting target from triple");
-        let target_machine = target
-            .create_target_machine(
-                &target_triple,
-                "generic",
-                "",
-                inkwell::OptimizationLevel::Aggressive,
-                RelocMode::Default,
-                CodeModel::Default,
-            )
-            .log_expect("Error creating target machine");
-        target_machine
-            .write_to_file(&module, inkwell::targets::FileType::Object, temp_path)
-            .log_expect("Error writing object file");
+        let mut objects = Vec::new();
+        let mut object_paths = Vec::new();
+        for (_, part) in &parts {
+            let object = tempfile::Builder::new()
+                .prefix("output-")
+                .suffix(".o")
+                .tempfile_in(output_dir)
+                .map_err(|e| {
+                    log::error!("Error creating temp file: {}", e);
+                    "Error creating temp file"
+                })?;
+            write_object_file(part, config, object.path())?;
+
+            // a kept object file is not deleted even if linking fails, when it is most useful for debugging
+            let object = object.into_temp_path();
+            if config.keep_temps {
+                let path = object.keep().map_err(|e| {
+                    log::error!("Error keeping temp file: {}", e);
+                    "Error keeping temp file"
+                })?;
+                log::info!("Keeping object file {}", path.display());
+                object_paths.push(path);
+            } else {
+                object_paths.push(object.to_path_buf());
+                objects.push(object);
+            }
+        }
 
         config.progress.set_message("Linking");
         config.progress.inc(1);
-        let clang_path = std::env::var("LLVM_SYS_160_PREFIX").log_expect("LLVM_SYS_160_PREFIX not set");
-        let clang_path = clang_path + "/bin/clang";
-        let output = Command::new(clang_path)
-            .arg(temp_path)
+        let output = match Command::new(clang_path)
+            .args(&object_paths)
             .arg("target/release/liblaspa_std.a")
             .arg("-o")
-            .arg("main")
+            .arg(&config.name)
             .arg("-lm")
             .output()
-            .log_expect("Failed to run clang");
+        {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!("Failed to run clang: {}", e);
+                return Err("Failed to run clang");
+            }
+        };
 
         if !output.status.success() {
             log::error!(
@@ -606,14 +1406,89 @@ impl Compile for LLVMCompiler<'_, '_> {
             return Err("Clang failed");
         }
 
-        config.progress.set_message("Deleting temp file");
-        config.progress.inc(1);
-        fs::remove_file(temp_path).log_expect("Error removing temp file");
+        if !objects.is_empty() {
+            config.progress.set_message("Deleting temp file");
+            config.progress.inc(1);
+            for object in objects {
+                object.close().map_err(|e| {
+                    log::error!("Error removing temp file: {}", e);
+                    "Error removing temp file"
+                })?;
+            }
+        }
 
         Ok(0.0)
     }
 }
 
+/// Split a module into one module per defined function, for [`CompileConfig::separate_objects`]. Each part is
+/// paired with its function's name, or `None` for `main`. A part keeps every definition, but the other
+/// functions are `available_externally`, so LLVM only emits its own function and declares the rest. Globals
+/// are likewise only emitted with `main`.
+fn split_module<'ctx>(module: &Module<'ctx>) -> Vec<(Option<String>, Module<'ctx>)> {
+    let functions = |module: &Module<'ctx>| {
+        std::iter::successors(module.get_first_function(), |f| f.get_next_function())
+            .filter(|function| function.count_basic_blocks() > 0)
+            .collect::<Vec<_>>()
+    };
+    functions(module)
+        .into_iter()
+        .map(|function| {
+            let name = function.get_name().to_string_lossy().into_owned();
+            let part = module.clone();
+            for other in functions(&part) {
+                if other.get_name() != function.get_name() {
+                    other.set_linkage(Linkage::AvailableExternally);
+                }
+            }
+            if name != MAIN {
+                let globals =
+                    std::iter::successors(part.get_first_global(), |g| g.get_next_global());
+                for global in globals {
+                    global.set_linkage(Linkage::AvailableExternally);
+                }
+            }
+            ((name != MAIN).then_some(name), part)
+        })
+        .collect()
+}
+
+/// Point the runtime functions a JIT-compiled module declares at the copies linked into laspa itself.
+fn map_runtime<'ctx>(module: &Module<'ctx>, execution_engine: &ExecutionEngine<'ctx>) {
+    let functions = [
+        ("laspa_print_f64", runtime::laspa_print_f64 as usize),
+        ("laspa_sqrt", runtime::laspa_sqrt as usize),
+        ("laspa_read_f64", runtime::laspa_read_f64 as usize),
+        ("laspa_seed_rand", runtime::laspa_seed_rand as usize),
+        ("laspa_rand", runtime::laspa_rand as usize),
+        ("laspa_randint", runtime::laspa_randint as usize),
+        ("laspa_clock", runtime::laspa_clock as usize),
+        ("laspa_hypot", runtime::laspa_hypot as usize),
+        ("laspa_dist", runtime::laspa_dist as usize),
+    ];
+    for (name, address) in functions {
+        if let Some(function) = module.get_function(name) {
+            execution_engine.add_global_mapping(&function, address);
+        }
+    }
+}
+
+/// The heavier pipeline for functions annotated with `#[optimize aggressive]`. It runs after the usual passes.
+fn optimize_hot_ir(fpm: &PassManager<FunctionValue>) {
+    fpm.add_promote_memory_to_register_pass();
+    fpm.add_sccp_pass();
+    fpm.add_early_cse_pass();
+    fpm.add_jump_threading_pass();
+    fpm.add_licm_pass();
+    fpm.add_ind_var_simplify_pass();
+    fpm.add_loop_deletion_pass();
+    fpm.add_tail_call_elimination_pass();
+    fpm.add_aggressive_dce_pass();
+    fpm.add_instruction_combining_pass();
+    fpm.add_cfg_simplification_pass();
+    fpm.initialize();
+}
+
 fn optimize_ir(fpm: &PassManager<FunctionValue>, opt_level: inkwell::OptimizationLevel) {
     match opt_level {
         inkwell::OptimizationLevel::None => return,
@@ -666,12 +1541,6 @@ fn optimize_ir(fpm: &PassManager<FunctionValue>, opt_level: inkwell::Optimizatio
     fpm.initialize();
 }
 
-fn compute_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
-}
-
 pub trait LogExpect<T> {
     fn log_expect(self, msg: &str) -> T;
 }
@@ -1,18 +1,24 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, io::Read, path::Path, process::Command, time::Duration};
 
 use clap::Parser;
 use env_logger::Builder;
 use indicatif::{ProgressBar, ProgressStyle};
-use laspa::{Compile, CompileConfig, Compiler, Interpreter};
+use laspa::{
+    ast_to_dot, backend_info, parse_source, unparse, Compile, CompileConfig, Compiler, FloatFormat,
+    FnExpr, Interpreter, Node, Syntax,
+};
 use log::LevelFilter;
 
 mod args;
 
+use args::{Emit, Mode};
+
 fn main() {
     let args = args::Args::parse();
 
     // Map verbosity count to log level
     let log_level = match args.verbose {
+        _ if args.quiet => LevelFilter::Error,
         0 => LevelFilter::Error,
         1 => LevelFilter::Warn,
         2 => LevelFilter::Info,
@@ -26,22 +32,113 @@ fn main() {
         .default_format()
         .init();
 
+    if args.backend_info {
+        println!("{}", backend_info());
+        return;
+    }
+    // clap only allows a missing file with --backend-info or --eval
+    let file = args.file.clone().unwrap_or_default();
+
     if args.optimization_level > 3 {
         log::error!("Error: optimization_level should be between 0 (none) and 3 (aggressive).");
         return;
     }
 
-    if args.jit {
+    let syntax = if args.infix {
+        Syntax::Infix
+    } else {
+        Syntax::Rpn
+    };
+
+    if args.format && args.infix {
+        log::error!("Error: only RPN source can be formatted");
+        std::process::exit(1);
+    }
+
+    if args.write && file == "-" {
+        log::error!("Error: stdin cannot be rewritten in place");
+        std::process::exit(1);
+    }
+
+    // `-` reads the program from stdin
+    let source = args.eval.clone().or_else(|| (file == "-").then(read_stdin));
+    // inline source is interpreted unless the JIT is asked for
+    let mode = match args.mode {
+        Mode::Jit => Mode::Jit,
+        _ if args.eval.is_some() => Mode::Interpret,
+        mode => mode,
+    };
+
+    // bitcode is written instead of an object file, so there is nothing to run
+    if args.emit.is_some() && matches!(mode, Mode::Interpret | Mode::Jit | Mode::Run) {
+        log::error!("Error: --emit only works with --mode object or --mode executable");
+        std::process::exit(1);
+    }
+
+    if args.ast || args.dump_symbols || args.format || args.graph.is_some() {
+        let source = source.unwrap_or_else(|| {
+            std::fs::read_to_string(&file).unwrap_or_else(|e| {
+                log::error!("Error reading file: {}", e);
+                std::process::exit(1);
+            })
+        });
+        match parse_source(&source, syntax) {
+            Ok(nodes) if args.ast => println!("{:#?}", nodes),
+            Ok(nodes) if args.dump_symbols => dump_symbols(&nodes),
+            Ok(nodes) if args.graph.is_some() => write_file(
+                args.graph.as_deref().unwrap_or_default(),
+                &ast_to_dot(&nodes),
+            ),
+            // formatting drops comments, so a file with comments is not overwritten
+            Ok(_) if args.write && source.split_whitespace().any(|token| token == "//") => {
+                log::error!("Error: files with comments cannot be rewritten in place");
+                std::process::exit(1);
+            }
+            Ok(nodes) if args.write => write_file(&file, &unparse(&nodes)),
+            Ok(nodes) => print!("{}", unparse(&nodes)),
+            Err(e) => {
+                log::error!("Error parsing source: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if mode == Mode::Jit {
         log::info!("Using JIT");
         log::warn!("Print IR is not supported with JIT");
     }
 
     let config = CompileConfig {
-        use_jit: args.jit,
+        use_jit: mode == Mode::Jit,
         optimization_level: args.optimization_level,
         show_ir: true,
         name: args.executable_name,
-        progress: ProgressBar::new(10),
+        progress: if args.quiet {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(10)
+        },
+        syntax,
+        target: args.target,
+        cpu: args.cpu,
+        features: args.features,
+        link: mode != Mode::Object,
+        keep_temps: args.keep_temps,
+        separate_objects: args.separate_objects,
+        emit_bitcode: args.emit == Some(Emit::Bitcode),
+        entry: args.entry,
+        implicit_bool_coercion: !args.no_bool_coercion,
+        implicit_else: !args.no_implicit_else,
+        deterministic: args.deterministic,
+        buffered_output: None,
+        float_format: if args.print_bits {
+            FloatFormat::Bits
+        } else {
+            FloatFormat::Default
+        },
+        print_all: args.print_all,
+        seed: args.seed,
     };
 
     config.progress.enable_steady_tick(Duration::from_millis(50));
@@ -51,19 +148,95 @@ fn main() {
         config.progress.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/white} {pos:>7}/{len:7} {msg} {spinner}").unwrap().progress_chars("==>-"));
     }
 
-    if args.interpret {
-        log::info!("Interpreting file {}", args.file);
-        let result = Interpreter::from_file(&args.file, &config);
-        log::trace!("Result: {:?}", result);
-    } else {
-        log::info!("Compiling file {}", args.file);
-        let result = Compiler::from_file(&args.file, &config);
-        if let Err(e) = result {
-            log::error!("Error: {}", e);
+    let result = match mode {
+        Mode::Interpret => {
+            log::info!("Interpreting file {}", file);
+            let result = match &source {
+                Some(source) => Interpreter::from_source(source, &config),
+                None => Interpreter::from_file(&file, &config),
+            };
+            log::trace!("Result: {:?}", result);
+            Some(result)
         }
-    }
+        Mode::Jit | Mode::Object | Mode::Executable | Mode::Run => {
+            log::info!("Compiling file {}", file);
+            let result = match &source {
+                Some(source) => Compiler::from_source(source, &config),
+                None => Compiler::from_file(&file, &config),
+            };
+            if let Err(e) = &result {
+                log::error!("Error: {}", e);
+                std::process::exit(1);
+            }
+            result.ok()
+        }
+    };
 
     config.progress.set_message("Done!");
     log::info!("Done");
     config.progress.finish();
+
+    if let (Some(_), Some(result)) = (&args.eval, result) {
+        println!("{}", config.float_format.format(result));
+    }
+
+    if mode == Mode::Run && result.is_some() {
+        // a bare name would be looked up in PATH
+        let executable = Path::new(".").join(&config.name);
+        log::info!("Running {}", executable.display());
+        let status = Command::new(&executable).status().unwrap_or_else(|e| {
+            log::error!("Error running executable: {}", e);
+            std::process::exit(1);
+        });
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+/// Write `contents` to `path`, exiting if it cannot be written.
+fn write_file(path: &str, contents: &str) {
+    std::fs::write(path, contents).unwrap_or_else(|e| {
+        log::error!("Error writing file: {}", e);
+        std::process::exit(1);
+    });
+}
+
+/// Read the whole program from stdin.
+fn read_stdin() -> String {
+    let mut source = String::new();
+    std::io::stdin()
+        .read_to_string(&mut source)
+        .unwrap_or_else(|e| {
+            log::error!("Error reading stdin: {}", e);
+            std::process::exit(1);
+        });
+    source
+}
+
+/// Print each function defined at the top level as `name/arity (params)`, sorted by name.
+fn dump_symbols(nodes: &[Node]) {
+    // like the function table, a later definition replaces an earlier one
+    let functions: BTreeMap<&str, &FnExpr> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::FnExpr(f) => Some((f.name.as_str(), f)),
+            _ => None,
+        })
+        .collect();
+
+    for (name, f) in functions {
+        let params = f
+            .args
+            .iter()
+            .map(|param| match param {
+                Node::Reference(name) => format!("&{name}"),
+                Node::Variable(name) => name.clone(),
+                Node::BindExpr(param) => match param.value.as_slice() {
+                    [Node::Number(n)] => format!("{}={}", param.name, n.0),
+                    _ => format!("{param:?}"),
+                },
+                other => format!("{other:?}"),
+            })
+            .collect::<Vec<_>>();
+        println!("{name}/{} ({})", params.len(), params.join(" "));
+    }
 }
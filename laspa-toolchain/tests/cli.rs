@@ -0,0 +1,454 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn ast_flag() {
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--ast")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/fib.laspa"
+        ))
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("FnExpr("));
+    assert!(stdout.contains("name: \"fib\""));
+    assert!(stdout.contains("BindExpr("));
+    assert!(stdout.contains("PrintStdoutExpr("));
+}
+
+#[test]
+fn dump_symbols_flag() {
+    let path = std::env::temp_dir().join("laspa-dump-symbols.laspa");
+    std::fs::write(
+        &path,
+        "fn sum (x y)\nreturn + x y\nend\nfn inc (&n)\n:= n + n 1\nend\nlet z sum (1 2)\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--dump-symbols")
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "inc/1 (&n)\nsum/2 (x y)\n"
+    );
+}
+
+#[test]
+fn graph_flag() {
+    let dot = std::env::temp_dir().join("laspa-graph.dot");
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--eval", "+ 1 2", "--graph"])
+        .arg(&dot)
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let graph = std::fs::read_to_string(&dot).unwrap();
+    std::fs::remove_file(&dot).unwrap();
+    assert!(graph.starts_with("digraph ast {"));
+    assert!(graph.contains("[label=\"BinaryExpr +\"]"));
+}
+
+#[test]
+fn format_flag() {
+    let path = std::env::temp_dir().join("laspa-format.laspa");
+    std::fs::write(&path, "let  x 0\nwhile < x 3;:= x + x 1\nend\n   print x").unwrap();
+
+    let run = |write: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_laspa"));
+        command.arg("--format");
+        if write {
+            command.arg("--write");
+        }
+        command.arg(&path).output().expect("Failed to run laspa")
+    };
+    let expected = "let x 0\nwhile < x 3\n    := x + x 1\nend\nprint x\n";
+
+    let output = run(false);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = run(true);
+    let formatted = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(formatted, expected);
+
+    // formatting would drop the comment, so the file is not rewritten
+    let source = "// count\nlet x 0\n";
+    std::fs::write(&path, source).unwrap();
+    let output = run(true);
+    let unchanged = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    assert_eq!(unchanged, source);
+}
+
+#[test]
+fn llvm_no_link_flag() {
+    let name = std::env::temp_dir().join("laspa-no-link");
+    let object = name.with_extension("o");
+    let _ = std::fs::remove_file(&object);
+
+    // without the LLVM prefix, linking would fail before clang could run
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--mode")
+        .arg("object")
+        .arg("-o")
+        .arg(&name)
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/fib.laspa"
+        ))
+        .env_remove("LLVM_SYS_160_PREFIX")
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    assert!(std::fs::metadata(&object).expect("No object file").len() > 0);
+    assert!(!name.exists());
+    std::fs::remove_file(&object).unwrap();
+}
+
+#[test]
+fn llvm_missing_llvm_prefix() {
+    let path = std::env::temp_dir().join("laspa-missing-prefix.laspa");
+    std::fs::write(&path, "print + 1 2\n").unwrap();
+    let name = std::env::temp_dir().join("laspa-missing-prefix");
+
+    let jit = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--mode", "jit"])
+        .arg(&path)
+        .env_remove("LLVM_SYS_160_PREFIX")
+        .output()
+        .expect("Failed to run laspa");
+    let executable = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("-o")
+        .arg(&name)
+        .arg(&path)
+        .env_remove("LLVM_SYS_160_PREFIX")
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(jit.status.success());
+    assert_eq!(String::from_utf8(jit.stdout).unwrap(), "3\n");
+    assert!(!executable.status.success());
+    let stderr = String::from_utf8(executable.stderr).unwrap();
+    assert!(stderr.contains("LLVM_SYS_160_PREFIX is not set"));
+    assert!(!name.exists());
+}
+
+#[test]
+fn llvm_jit_mode() {
+    let path = std::env::temp_dir().join("laspa-jit-mode.laspa");
+    std::fs::write(&path, "print + 1 2\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--mode")
+        .arg("jit")
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+}
+
+#[test]
+fn llvm_jit_operand_order() {
+    let path = std::env::temp_dir().join("laspa-operand-order.laspa");
+    std::fs::write(
+        &path,
+        "let x 1\nprint - do print 1 1 end do print 2 2 end\nprint + do := x * x 10 x end do := x + x 1 x end\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--mode", "jit"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n-1\n21\n");
+}
+
+#[test]
+fn backend_info_flag() {
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--backend-info")
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("LLVM"));
+}
+
+#[test]
+fn invalid_mode() {
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--mode")
+        .arg("compile")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/fib.laspa"
+        ))
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("possible values: interpret, jit, object, executable, run"));
+}
+
+#[test]
+fn emit_needs_compiled_mode() {
+    for args in [
+        &["--emit", "bitcode", "-e", "return 1"][..],
+        &["--emit", "bitcode", "--mode", "jit", "-e", "return 1"],
+        &["--emit", "bitcode", "--mode", "run", "-"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .expect("Failed to run laspa");
+
+        assert!(!output.status.success(), "{args:?} succeeded");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("--emit only works with"),
+            "{args:?}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn print_all_flag() {
+    let path = std::env::temp_dir().join("laspa-print-all.laspa");
+    std::fs::write(&path, "1;2;3").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--mode", "interpret", "--print-all"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n3\n");
+}
+
+#[test]
+fn quiet_flag() {
+    let path = std::env::temp_dir().join("laspa-quiet.laspa");
+    std::fs::write(&path, "print + 1 2").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--mode", "interpret", "--quiet", "-vvv"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn eval_flag() {
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["-e", "return + 2 3"])
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "5\n");
+}
+
+#[test]
+fn print_bits_flag() {
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--print-bits", "-e", "print 1.0"])
+        .output()
+        .expect("Failed to run laspa");
+
+    assert!(output.status.success());
+    // `-e` then prints the result, which is 0 because `print` has no value
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "0x3ff0000000000000\n0x0000000000000000\n"
+    );
+}
+
+#[test]
+fn stdin_source() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .args(["--mode", "interpret", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run laspa");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"let x + 1 2\nprint x\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+}
+
+#[test]
+fn print_before_error() {
+    let path = std::env::temp_dir().join("laspa-print-before-error.laspa");
+    std::fs::write(&path, "print 1\nprint 2\nreturn + x 1\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .arg("--mode")
+        .arg("interpret")
+        .arg(&path)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n");
+}
+
+#[test]
+#[ignore = "needs LLVM_SYS_160_PREFIX and `cargo build --release -p laspa_std`"]
+fn llvm_run_mode() {
+    let dir = std::env::temp_dir();
+    let source = dir.join("laspa-run-mode.laspa");
+    let name = dir.join("laspa-run-mode");
+    std::fs::write(&source, "print 7\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/.."))
+        .arg("--mode")
+        .arg("run")
+        .arg("-o")
+        .arg(&name)
+        .arg(&source)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&source).unwrap();
+    std::fs::remove_file(&name).unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("7\n"));
+}
+
+#[test]
+#[ignore = "needs LLVM_SYS_160_PREFIX and `cargo build --release -p laspa_std`"]
+fn llvm_keep_temps() {
+    let dir = std::env::temp_dir().join("laspa-keep-temps");
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("main.laspa");
+    std::fs::write(&source, "print 7\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/.."))
+        .arg("--keep-temps")
+        .arg("-o")
+        .arg(dir.join("main"))
+        .arg(&source)
+        .output()
+        .expect("Failed to run laspa");
+    let objects: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "o"))
+        .collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(objects.len(), 1);
+}
+
+#[test]
+#[ignore = "needs LLVM_SYS_160_PREFIX and `cargo build --release -p laspa_std`"]
+fn llvm_separate_objects() {
+    let dir = std::env::temp_dir().join("laspa-separate-objects-link");
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("main.laspa");
+    let name = dir.join("main");
+    // `add` reads a global, which is only defined in `main`'s object
+    std::fs::write(
+        &source,
+        "global base 1\nfn add (x y)\nreturn + + x y base\nend\nfn twice (x)\nreturn * x 2\nend\n\
+         let n 2\nlet s add (n 1)\nprint twice (s)\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/.."))
+        .arg("--separate-objects")
+        .arg("--keep-temps")
+        .arg("-o")
+        .arg(&name)
+        .arg(&source)
+        .output()
+        .expect("Failed to run laspa");
+    let objects = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            let path = entry.as_ref().unwrap().path();
+            path.extension().is_some_and(|ext| ext == "o")
+        })
+        .count();
+    assert!(output.status.success());
+
+    let output = Command::new(&name)
+        .output()
+        .expect("Failed to run executable");
+    std::fs::remove_dir_all(&dir).unwrap();
+    // one object for each function and one for `main`
+    assert_eq!(objects, 3);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "8\n");
+}
+
+#[test]
+#[ignore = "needs LLVM_SYS_160_PREFIX and `cargo build --release -p laspa_std`"]
+fn llvm_link_runtime() {
+    let dir = std::env::temp_dir();
+    let source = dir.join("laspa-link-runtime.laspa");
+    let name = dir.join("laspa-link-runtime");
+    std::fs::write(&source, "print sqrt 16\n").unwrap();
+
+    // the runtime library is linked from the workspace's target directory
+    let output = Command::new(env!("CARGO_BIN_EXE_laspa"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/.."))
+        .arg("-o")
+        .arg(&name)
+        .arg(&source)
+        .output()
+        .expect("Failed to run laspa");
+    std::fs::remove_file(&source).unwrap();
+    assert!(output.status.success());
+
+    let output = Command::new(&name)
+        .output()
+        .expect("Failed to run executable");
+    std::fs::remove_file(&name).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n");
+}